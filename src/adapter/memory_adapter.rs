@@ -0,0 +1,198 @@
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::Adapter;
+use attribute::{Attribute, Attributes};
+use error::Result;
+use model::{Model, Record, RecordSet};
+use query::{Query, SortOrder};
+use serializer::{Serializer, SimpleSerializer};
+
+/// Keeps rows in memory, used by the tests.
+pub struct MemoryAdapter {
+    tables: RefCell<HashMap<&'static str, Vec<Attributes>>>,
+    next_id: Cell<i32>,
+    pub queries: Cell<usize>,
+    /// The statements given to `execute_batch`, which are not run.
+    pub executed: RefCell<Vec<String>>,
+}
+
+impl MemoryAdapter {
+    pub fn new() -> MemoryAdapter {
+        MemoryAdapter {
+            tables: RefCell::new(HashMap::new()),
+            next_id: Cell::new(1),
+            queries: Cell::new(0),
+            executed: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn rows(&self, model: Model) -> Vec<Attributes> {
+        self.tables.borrow().get(&model.ty[..]).cloned().unwrap_or_else(Vec::new)
+    }
+
+    // Whether each row of the table matches the filter of `query`, failing before anything is
+    // written if a row can't be read.
+    fn matching(&self, query: &Query) -> Result<Vec<bool>> {
+        if let Some(ref filter) = query.filter {
+            try!(filter.check_local());
+        }
+        self.rows(query.model).into_iter().map(|row| {
+            let record = try!(SimpleSerializer.extract(query.model, row));
+            Ok(query.filter.as_ref().map_or(true, |filter| filter.matches(&record)))
+        }).collect()
+    }
+
+    fn position(&self, model: Model, key: &[Attribute]) -> Option<usize> {
+        if key.len() != model.primary_key.len() {
+            return None;
+        }
+        self.tables.borrow().get(&model.ty[..]).and_then(|rows| {
+            rows.iter().position(|row| model.primary_key.iter().zip(key).all(|(name, value)| row.get(name) == Some(value)))
+        })
+    }
+}
+
+impl Adapter for MemoryAdapter {
+    fn query_with<S: Serializer>(&self, query: &Query, serializer: &S) -> Result<RecordSet> {
+        self.queries.set(self.queries.get() + 1);
+        let condition = query.condition();
+        if let Some(ref filter) = condition {
+            try!(filter.check_local());
+        }
+        let ordering = query.ordering();
+        let mut records: Vec<Record> = vec![];
+        for row in self.rows(query.model) {
+            let record = try!(serializer.extract(query.model, row));
+            if condition.as_ref().map_or(true, |filter| filter.matches(&record)) {
+                records.push(record);
+            }
+        }
+        records.sort_by(|a, b| {
+            for &(name, order) in &ordering {
+                let ordering = match (a.value(name), b.value(name)) {
+                    (Some(a), Some(b)) => a.compare(b).unwrap_or(Ordering::Equal),
+                    _ => Ordering::Equal,
+                };
+                if ordering != Ordering::Equal {
+                    return match order {
+                        SortOrder::ASC => ordering,
+                        SortOrder::DESC => ordering.reverse(),
+                    };
+                }
+            }
+            Ordering::Equal
+        });
+        let records = records.into_iter()
+                          .skip(query.offset.unwrap_or(0) as usize)
+                          .take(query.limit.map_or(usize::max_value(), |limit| limit as usize))
+                          .collect();
+        Ok(RecordSet::new(records))
+    }
+
+    fn count(&self, query: &Query) -> Result<u64> {
+        Ok(try!(self.matching(query)).into_iter().filter(|&matches| matches).count() as u64)
+    }
+
+    fn insert(&self, model: Model, attributes: &Attributes) -> Result<Attributes> {
+        let mut row = attributes.clone();
+        if let (Some(id_column), Some(ty)) = (model.id_column(), model.id_type()) {
+            if model.key_generation.by_database() && row.get(id_column).map_or(true, |id| id.is_null()) {
+                let id: Attribute = self.next_id.get().into();
+                if let Some(id) = id.coerce(ty) {
+                    row.insert(id_column.to_string(), id);
+                    self.next_id.set(self.next_id.get() + 1);
+                }
+            }
+        }
+        self.tables.borrow_mut().entry(&model.ty[..]).or_insert_with(Vec::new).push(row.clone());
+        Ok(row)
+    }
+
+    fn insert_all(&self, model: Model, rows: &[Attributes]) -> Result<Vec<Attributes>> {
+        rows.iter().map(|row| self.insert(model, row)).collect()
+    }
+
+    fn update(&self, model: Model, key: &[Attribute], attributes: &Attributes) -> Result<u64> {
+        match self.position(model, key) {
+            Some(i) => {
+                self.tables.borrow_mut().get_mut(&model.ty[..]).unwrap()[i].extend(attributes.clone());
+                Ok(1)
+            },
+            None => Ok(0),
+        }
+    }
+
+    fn delete(&self, model: Model, key: &[Attribute]) -> Result<u64> {
+        match self.position(model, key) {
+            Some(i) => {
+                self.tables.borrow_mut().get_mut(&model.ty[..]).unwrap().remove(i);
+                Ok(1)
+            },
+            None => Ok(0),
+        }
+    }
+
+    fn update_all(&self, query: &Query, attributes: &Attributes) -> Result<u64> {
+        let matching = try!(self.matching(query));
+        let mut count = 0;
+        if let Some(rows) = self.tables.borrow_mut().get_mut(&query.model.ty[..]) {
+            for (row, matches) in rows.iter_mut().zip(matching) {
+                if matches {
+                    row.extend(attributes.clone());
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    fn delete_all(&self, query: &Query) -> Result<u64> {
+        let mut matching = try!(self.matching(query)).into_iter();
+        let mut count = 0;
+        if let Some(rows) = self.tables.borrow_mut().get_mut(&query.model.ty[..]) {
+            let len = rows.len();
+            rows.retain(|_| !matching.next().unwrap_or(false));
+            count = (len - rows.len()) as u64;
+        }
+        Ok(count)
+    }
+
+    fn upsert(&self, model: Model, rows: &[Attributes], key: &[&str], update: &[&str]) -> Result<Vec<Attributes>> {
+        let mut stored = vec![];
+        for row in rows {
+            // Like in SQL, nulls never conflict
+            let conflict = self.rows(model).iter().position(|other| key.iter().all(|name| {
+                match (row.get(*name), other.get(*name)) {
+                    (Some(a), Some(b)) => !a.is_null() && a == b,
+                    _ => false,
+                }
+            }));
+            match conflict {
+                Some(_) if update.len() == 0 => {},
+                Some(i) => {
+                    let mut tables = self.tables.borrow_mut();
+                    let other = &mut tables.get_mut(&model.ty[..]).unwrap()[i];
+                    for name in update {
+                        if let Some(attribute) = row.get(*name) {
+                            other.insert(name.to_string(), attribute.clone());
+                        }
+                    }
+                    stored.push(other.clone());
+                },
+                None => stored.push(try!(self.insert(model, row))),
+            }
+        }
+        Ok(stored)
+    }
+
+    fn execute_batch(&self, sql: &str) -> Result<()> {
+        self.executed.borrow_mut().push(sql.to_string());
+        Ok(())
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T> where F: FnOnce(&Self) -> Result<T> {
+        f(self)
+    }
+}
@@ -1,11 +1,57 @@
-use model::RecordSet;
+use attribute::{Attribute, Attributes};
+use error::Result;
+use model::{Model, RecordSet};
 use query::Query;
+use serializer::{Serializer, SimpleSerializer};
 
 #[cfg(feature="postgres-adapter")]
 pub mod postgres_adapter;
+#[cfg(feature="postgres-adapter")]
+pub mod postgres_schema;
+
+#[cfg(test)]
+pub mod memory_adapter;
 
 pub trait Adapter {
-    fn query(&self, query: &Query) -> Option<RecordSet>;
+    /// Runs `query`, mapping the rows to records with `serializer`.
+    fn query_with<S: Serializer>(&self, query: &Query, serializer: &S) -> Result<RecordSet>;
+
+    fn query(&self, query: &Query) -> Result<RecordSet> {
+        self.query_with(query, &SimpleSerializer)
+    }
+
+    /// Counts the rows matching the filter of `query`, ignoring its sorting, offset and limit.
+    fn count(&self, query: &Query) -> Result<u64>;
+
+    /// Inserts a row and returns it as stored, including the generated primary key.
+    fn insert(&self, model: Model, attributes: &Attributes) -> Result<Attributes>;
+
+    /// Inserts many rows at once and returns them as stored, in order.
+    fn insert_all(&self, model: Model, rows: &[Attributes]) -> Result<Vec<Attributes>>;
+
+    /// Updates the row identified by `key`, one attribute per primary key column, returns the number
+    /// of affected rows.
+    fn update(&self, model: Model, key: &[Attribute], attributes: &Attributes) -> Result<u64>;
+
+    /// Deletes the row identified by `key`, returns the number of affected rows.
+    fn delete(&self, model: Model, key: &[Attribute]) -> Result<u64>;
+
+    /// Sets `attributes` on the rows matching the filter of `query`, returns the number of affected
+    /// rows.
+    fn update_all(&self, query: &Query, attributes: &Attributes) -> Result<u64>;
+
+    /// Deletes the rows matching the filter of `query`, returns the number of affected rows.
+    fn delete_all(&self, query: &Query) -> Result<u64>;
+
+    /// Inserts rows, or updates the `update` columns of the stored rows they conflict with on the
+    /// unique `key` instead. Returns the inserted and updated rows as stored.
+    fn upsert(&self, model: Model, rows: &[Attributes], key: &[&str], update: &[&str]) -> Result<Vec<Attributes>>;
+
+    /// Runs statements which return no rows, such as DDL.
+    fn execute_batch(&self, sql: &str) -> Result<()>;
+
+    /// Runs `f` in a transaction, which is committed only if `f` returns `Ok`.
+    fn transaction<T, F>(&self, f: F) -> Result<T> where F: FnOnce(&Self) -> Result<T>;
 }
 
 /*
@@ -1,5 +1,7 @@
 extern crate postgres;
 
+use std::cmp;
+use std::collections::BTreeSet;
 use std::io::prelude::*;
 
 use self::postgres::Connection;
@@ -8,8 +10,11 @@ use self::postgres::error::Error as PostgresError;
 
 use super::Adapter;
 use attribute::{Attribute, Attributes};
-use model::{Record, RecordSet};
+use error::{self, Error};
+use model::{Model, Record, RecordSet};
+use relationship::{self, RelationshipType};
 use query::{Query, Filter, SortOrder};
+use serializer::Serializer;
 
 macro_rules! accepts {
     ($($expected:pat),+) => (
@@ -69,10 +74,10 @@ impl PostgresAdapter {
         }
     }
 
-    pub fn query_raw(&self, sql: &str, params: &[&Attribute]) -> Option<Vec<Attributes>> {
-        let stmt = self.conn.prepare(&sql).unwrap();
+    pub fn query_raw(&self, sql: &str, params: &[&Attribute]) -> error::Result<Vec<Attributes>> {
+        let stmt = try!(self.conn.prepare(&sql));
         let params: Vec<&ToSql> = params.into_iter().map(|param| *param as &ToSql).collect();
-        let rows = stmt.query(params.as_slice()).unwrap();
+        let rows = try!(stmt.query(params.as_slice()));
 
         let mut attributes = vec![];
 
@@ -86,7 +91,34 @@ impl PostgresAdapter {
             }));
         }
 
-        Some(attributes)
+        Ok(attributes)
+    }
+
+    /// Loads records with `COPY ... FROM STDIN`, which is much faster than inserting them for
+    /// large loads, but doesn't return the stored rows. The records are validated and given keys
+    /// as by `insert_all`. Either every record has a key or none does, since `COPY` can't take
+    /// default values for some rows only.
+    pub fn copy_in(&self, model: Model, records: Vec<Record>) -> error::Result<u64> {
+        let rows = try!(model.insert_rows(&records));
+        let columns: Vec<&str> = columns_of(model, &rows).into_iter().filter(|name| {
+            model.id_column() != Some(*name) || rows.iter().any(|row| row.get(*name).map_or(false, |id| !id.is_null()))
+        }).collect();
+        let data: String = rows.iter().map(|row| copy_row(&columns, row)).collect();
+
+        let stmt = try!(self.conn.prepare(&format!("COPY {} ({}) FROM STDIN", model.ty, columns.join(","))));
+        Ok(try!(stmt.copy_in(&[], &mut data.as_bytes())))
+    }
+
+    pub fn execute_raw(&self, sql: &str, params: &[&Attribute]) -> error::Result<u64> {
+        let stmt = try!(self.conn.prepare(&sql));
+        let params: Vec<&ToSql> = params.into_iter().map(|param| *param as &ToSql).collect();
+        Ok(try!(stmt.execute(params.as_slice())))
+    }
+}
+
+impl From<PostgresError> for Error {
+    fn from(err: PostgresError) -> Error {
+        Error::Adapter(format!("{}", err))
     }
 }
 
@@ -128,39 +160,200 @@ impl FromSql for Attribute {
     accepts_for_attribute!();
 }
 
+// Resolves the attribute names of a query to columns. Dotted paths through `BelongsTo` and
+// `HasOne` relationships join the related tables, aliased by their path, while paths through
+// `HasMany` relationships are tested in `EXISTS` subqueries, as joining them would repeat rows.
+struct Scope {
+    // Names are used as they are without a model.
+    model: Option<Model>,
+    alias: String,
+    path: String,
+    qualify: bool,
+    joins: Vec<(String, String)>,
+    // The alias of the enclosing query in a subquery, see `Filter::EqualOuter`.
+    outer: Option<String>,
+    depth: usize,
+}
+
+enum Column {
+    Plain(String),
+    // The rest of a path after a `HasMany` relationship, along with the subquery to test it in.
+    Many(Scope, String, String),
+}
+
+impl Scope {
+    // Columns are only qualified if they need to be, i.e. when the query has joins.
+    fn new(model: Model, qualify: bool) -> Scope {
+        Scope {
+            model: Some(model),
+            alias: model.ty.to_string(),
+            path: String::new(),
+            qualify: qualify,
+            joins: vec![],
+            outer: None,
+            depth: 0,
+        }
+    }
+
+    // Subqueries are aliased by their depth, so that they can query the same table as the
+    // enclosing query.
+    fn subquery(&self, model: Model) -> Scope {
+        let path = format!("s{}", self.depth + 1);
+        Scope {
+            model: Some(model),
+            alias: format!("\"{}\"", path),
+            path: path,
+            qualify: true,
+            joins: vec![],
+            outer: Some(self.alias.clone()),
+            depth: self.depth + 1,
+        }
+    }
+
+    #[cfg(test)]
+    fn plain() -> Scope {
+        Scope {
+            model: None,
+            alias: String::new(),
+            path: String::new(),
+            qualify: false,
+            joins: vec![],
+            outer: None,
+            depth: 0,
+        }
+    }
+
+    fn column(&self, name: &str) -> String {
+        if self.qualify {
+            format!("{}.{}", self.alias, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn joins(&self) -> String {
+        self.joins.iter().map(|&(_, ref join)| join.as_str()).collect()
+    }
+
+    fn resolve(&mut self, name: &str) -> Column {
+        let mut model = match self.model {
+            Some(model) => model,
+            None => return Column::Plain(name.to_string()),
+        };
+        let mut alias = self.alias.clone();
+        let mut path = self.path.clone();
+
+        let segments: Vec<&str> = name.split('.').collect();
+        let (attribute, relationships) = segments.split_last().unwrap();
+        for (i, segment) in relationships.iter().enumerate() {
+            let target_path = if path.len() > 0 { format!("{}.{}", path, segment) } else { segment.to_string() };
+            let target_alias = format!("\"{}\"", target_path);
+            let (target, on) = match model.relationships.get(*segment) {
+                Some(&RelationshipType::BelongsTo(target)) => {
+                    (target, join_condition(&target_alias, &target.primary_key, &alias, &relationship::foreign_keys(segment, target)))
+                },
+                Some(&RelationshipType::HasOne(target)) => {
+                    (target, join_condition(&target_alias, &model.foreign_key_in(target), &alias, &model.primary_key))
+                },
+                Some(&RelationshipType::HasMany(target)) => {
+                    let condition = join_condition(&target_alias, &model.foreign_key_in(target), &alias, &model.primary_key);
+                    let subquery = Scope {
+                        model: Some(target),
+                        alias: target_alias,
+                        path: target_path,
+                        qualify: true,
+                        joins: vec![],
+                        outer: self.outer.clone(),
+                        depth: self.depth,
+                    };
+                    let rest = segments[i + 1..].join(".");
+                    return Column::Many(subquery, condition, rest);
+                },
+                // Paths are checked by `Query` beforehand, leave it to Postgres otherwise
+                None => return Column::Plain(name.to_string()),
+            };
+
+            if !self.joins.iter().any(|&(ref joined, _)| *joined == target_path) {
+                self.joins.push((target_path.clone(), format!(" LEFT JOIN {} AS {} ON {}", target.ty, target_alias, on)));
+            }
+            model = target;
+            alias = target_alias;
+            path = target_path;
+        }
+
+        if self.qualify {
+            Column::Plain(format!("{}.{}", alias, attribute))
+        } else {
+            Column::Plain(attribute.to_string())
+        }
+    }
+}
+
+// Pairs up the columns of both sides, e.g. `a.x=b.y AND a.z=b.w` for composite keys.
+fn join_condition(left: &str, left_columns: &[String], right: &str, right_columns: &[String]) -> String {
+    let conditions: Vec<String> = left_columns.iter().zip(right_columns).map(|(left_column, right_column)| {
+        format!("{}.{}={}.{}", left, left_column, right, right_column)
+    }).collect();
+    conditions.join(" AND ")
+}
+
 fn build_sql<'a>(query: &'a Query) -> (String, Vec<&'a Attribute>) {
     let mut params = vec![];
-    let mut sql = format!("SELECT {} FROM {}", match query.fields {
-        None => "*".to_string(),
-        Some(ref fields) => fields.iter().fold(String::new(), |mut fields, field| {
+    let mut scope = Scope::new(query.model, query.has_paths());
+    let sql = build_select(&mut scope, &mut params, query, None, query.condition().as_ref(), &query.ordering());
+    (sql, params)
+}
+
+// Builds the `SELECT` of `query` with the given filter and ordering, selecting `columns` instead of
+// the fields of the query if given.
+fn build_select<'a>(scope: &mut Scope,
+                    params: &mut Vec<&'a Attribute>,
+                    query: &Query,
+                    columns: Option<String>,
+                    filter: Option<&Filter<'a>>,
+                    sort: &[(&str, SortOrder)]) -> String {
+    let fields = match (columns, query.fields.as_ref()) {
+        (Some(columns), _) => columns,
+        (None, None) => scope.column("*"),
+        (None, Some(fields)) => fields.iter().fold(String::new(), |mut fields, field| {
             if fields.len () > 0 {
                 fields.push(',');
             }
-            fields.push_str(field);
+            fields.push_str(&scope.column(field));
             fields
         })
-    }, query.model.ty);
+    };
 
-    if let Some(ref filter) = query.filter {
-        sql.push_str(" WHERE ");
-        build_filter(&mut sql, &mut params, filter);
+    let mut where_sql = String::new();
+    if let Some(filter) = filter {
+        where_sql.push_str(" WHERE ");
+        build_filter(scope, &mut where_sql, params, filter);
     }
 
-    if let Some(ref sort) = query.sort {
+    let mut order_sql = String::new();
+    if sort.len() > 0 {
         let order_str = sort.iter().fold(String::new(), |mut order_str, &(ref name, ref order)| {
             if order_str.len() > 0 {
                 order_str.push(',');
             }
-            order_str.push_str(format!("{} {}", name, match order {
+            // Sorting through `HasMany` relationships is refused by `Query`
+            let column = match scope.resolve(name) {
+                Column::Plain(column) => column,
+                Column::Many(..) => name.to_string(),
+            };
+            order_str.push_str(format!("{} {}", column, match order {
                 &SortOrder::ASC => "ASC",
                 &SortOrder::DESC => "DESC",
             }).as_str());
 
             order_str
         });
-        sql.push_str(format!(" ORDER BY {}", order_str).as_str());
+        order_sql.push_str(format!(" ORDER BY {}", order_str).as_str());
     }
 
+    let alias = if scope.depth > 0 { format!(" AS {}", scope.alias) } else { String::new() };
+    let mut sql = format!("SELECT {} FROM {}{}{}{}{}", fields, query.model.ty, alias, scope.joins(), where_sql, order_sql);
+
     if let Some(ref limit) = query.limit {
         sql.push_str(format!(" LIMIT {}", limit).as_str());
     }
@@ -169,18 +362,56 @@ fn build_sql<'a>(query: &'a Query) -> (String, Vec<&'a Attribute>) {
         sql.push_str(format!(" OFFSET {}", offset).as_str());
     }
 
+    sql
+}
+
+// Subqueries are filtered and sorted as given, cursors don't apply to them.
+fn build_subquery<'a>(scope: &Scope, params: &mut Vec<&'a Attribute>, query: &Query<'a>, select_key: bool) -> String {
+    let mut subquery = scope.subquery(query.model);
+    let columns = if select_key {
+        match query.fields.as_ref().and_then(|fields| fields.first()) {
+            Some(field) => subquery.column(field),
+            None => {
+                let key: Vec<String> = query.model.primary_key.iter().map(|name| subquery.column(name)).collect();
+                key.join(",")
+            },
+        }
+    } else {
+        "1".to_string()
+    };
+    let sort = query.sort.clone().unwrap_or_else(Vec::new);
+    build_select(&mut subquery, params, query, Some(columns), query.filter.as_ref(), &sort)
+}
+
+fn build_count<'a>(query: &'a Query) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let mut scope = Scope::new(query.model, query.has_paths());
+
+    let mut where_sql = String::new();
+    if let Some(ref filter) = query.filter {
+        where_sql.push_str(" WHERE ");
+        build_filter(&mut scope, &mut where_sql, &mut params, filter);
+    }
+
+    let sql = format!("SELECT COUNT(*) AS count FROM {}{}{}", query.model.ty, scope.joins(), where_sql);
     (sql, params)
 }
 
-fn build_filter<'a>(sql: &mut String, params: &mut Vec<&'a Attribute>, filter: &Filter<'a>) {
+fn build_filter<'a>(scope: &mut Scope, sql: &mut String, params: &mut Vec<&'a Attribute>, filter: &Filter<'a>) {
     match filter {
-        &Filter::IsNull(name) => sql.push_str(format!("{} IS NULL", name).as_str()),
-        &Filter::IsNotNull(name) => sql.push_str(format!("{} IS NOT NULL", name).as_str()),
-        &Filter::Equal(name, attribute) => {
+        &Filter::IsNull(name) => build_predicate(scope, sql, params, name, |sql, _, column| {
+            sql.push_str(format!("{} IS NULL", column).as_str())
+        }),
+        &Filter::IsNotNull(name) => build_predicate(scope, sql, params, name, |sql, _, column| {
+            sql.push_str(format!("{} IS NOT NULL", column).as_str())
+        }),
+        &Filter::Equal(name, attribute) => build_predicate(scope, sql, params, name, |sql, params, column| {
             params.push(attribute);
-            sql.push_str(format!("{}=${}", name, params.len()).as_str());
-        },
-        &Filter::In(name, ref attributes) => {
+            sql.push_str(format!("{}=${}", column, params.len()).as_str());
+        }),
+        // `x IN ()` is a syntax error, while an empty list matches nothing
+        &Filter::In(_, ref attributes) if attributes.len() == 0 => sql.push_str("FALSE"),
+        &Filter::In(name, ref attributes) => build_predicate(scope, sql, params, name, |sql, params, column| {
             let (params_str, _) = attributes.iter().fold((String::new(), params), |(mut params_str, params), attribute| {
                 if params_str.len() > 0 {
                     params_str.push(',')
@@ -189,38 +420,345 @@ fn build_filter<'a>(sql: &mut String, params: &mut Vec<&'a Attribute>, filter: &
                 params_str.push_str(format!("${}", params.len()).as_str());
                 (params_str, params)
             });
-            sql.push_str(format!("{} IN ({})", name, params_str).as_str());
+            sql.push_str(format!("{} IN ({})", column, params_str).as_str());
+        }),
+        &Filter::GreaterThan(name, attribute) => build_predicate(scope, sql, params, name, |sql, params, column| {
+            params.push(attribute);
+            sql.push_str(format!("{}>${}", column, params.len()).as_str());
+        }),
+        &Filter::LessThan(name, attribute) => build_predicate(scope, sql, params, name, |sql, params, column| {
+            params.push(attribute);
+            sql.push_str(format!("{}<${}", column, params.len()).as_str());
+        }),
+        // The subquery is built first, which is fine as the column itself takes no parameters
+        &Filter::InQuery(name, ref query) => {
+            let subquery = build_subquery(scope, params, query, true);
+            build_predicate(scope, sql, params, name, |sql, _, column| {
+                sql.push_str(format!("{} IN ({})", column, subquery).as_str())
+            });
+        },
+        &Filter::Exists(ref query) => {
+            let subquery = build_subquery(scope, params, query, false);
+            sql.push_str(format!("EXISTS ({})", subquery).as_str());
+        },
+        &Filter::EqualOuter(name, outer) => {
+            let outer = match scope.outer {
+                Some(ref alias) => format!("{}.{}", alias, outer),
+                None => outer.to_string(),
+            };
+            build_predicate(scope, sql, params, name, |sql, _, column| {
+                sql.push_str(format!("{}={}", column, outer).as_str())
+            });
         },
         &Filter::And(ref f1, ref f2) => {
             sql.push('(');
-            build_filter(sql, params, f1);
+            build_filter(scope, sql, params, f1);
             sql.push_str(") AND (");
-            build_filter(sql, params, f2);
+            build_filter(scope, sql, params, f2);
             sql.push(')');
         },
         &Filter::Or(ref f1, ref f2) => {
             sql.push('(');
-            build_filter(sql, params, f1);
+            build_filter(scope, sql, params, f1);
             sql.push_str(") OR (");
-            build_filter(sql, params, f2);
+            build_filter(scope, sql, params, f2);
             sql.push(')');
         },
     }
 }
 
+// Renders `predicate` for the column `name` resolves to, wrapped in an `EXISTS` subquery for each
+// `HasMany` relationship on the way, so that it matches if any related record does.
+fn build_predicate<'a, F>(scope: &mut Scope, sql: &mut String, params: &mut Vec<&'a Attribute>, name: &str, predicate: F)
+    where F: FnOnce(&mut String, &mut Vec<&'a Attribute>, &str)
+{
+    match scope.resolve(name) {
+        Column::Plain(column) => predicate(sql, params, &column),
+        Column::Many(mut subquery, condition, rest) => {
+            let mut where_sql = format!("{} AND ", condition);
+            build_predicate(&mut subquery, &mut where_sql, params, &rest, predicate);
+            let ty = &subquery.model.unwrap().ty;
+            sql.push_str(format!("EXISTS (SELECT 1 FROM {} AS {}{} WHERE {})",
+                                 ty, subquery.alias, subquery.joins(), where_sql).as_str());
+        },
+    }
+}
+
+fn build_insert<'a>(model: Model, attributes: &'a Attributes) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let mut columns = String::new();
+    let mut values = String::new();
+
+    for (name, attribute) in attributes {
+        if columns.len() > 0 {
+            columns.push(',');
+            values.push(',');
+        }
+        params.push(attribute);
+        columns.push_str(name);
+        values.push_str(format!("${}", params.len()).as_str());
+    }
+
+    let sql = if params.len() > 0 {
+        format!("INSERT INTO {} ({}) VALUES ({}) RETURNING *", model.ty, columns, values)
+    } else {
+        format!("INSERT INTO {} DEFAULT VALUES RETURNING *", model.ty)
+    };
+
+    (sql, params)
+}
+
+// `a=$1 AND b=$2` for the primary key columns.
+fn build_key_condition<'a>(model: Model, key: &'a [Attribute], params: &mut Vec<&'a Attribute>) -> String {
+    let conditions: Vec<String> = model.primary_key.iter().zip(key).map(|(name, attribute)| {
+        params.push(attribute);
+        format!("{}=${}", name, params.len())
+    }).collect();
+    conditions.join(" AND ")
+}
+
+fn build_update<'a>(model: Model, key: &'a [Attribute], attributes: &'a Attributes) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let mut assignments = String::new();
+
+    for (name, attribute) in attributes {
+        if assignments.len() > 0 {
+            assignments.push(',');
+        }
+        params.push(attribute);
+        assignments.push_str(format!("{}=${}", name, params.len()).as_str());
+    }
+
+    let condition = build_key_condition(model, key, &mut params);
+    let sql = format!("UPDATE {} SET {} WHERE {}", model.ty, assignments, condition);
+
+    (sql, params)
+}
+
+// `UPDATE` and `DELETE` can't join, so with paths through relationships the matching rows are
+// selected by primary key in a subquery.
+fn build_where<'a>(query: &'a Query, params: &mut Vec<&'a Attribute>) -> String {
+    let filter = match query.filter {
+        Some(ref filter) => filter,
+        None => return String::new(),
+    };
+
+    if query.has_paths() {
+        let mut scope = Scope::new(query.model, true);
+        let key: Vec<String> = query.model.primary_key.iter().map(|name| scope.column(name)).collect();
+        let subquery = build_select(&mut scope, params, query, Some(key.join(",")), Some(filter), &[]);
+        if query.model.primary_key.len() == 1 {
+            format!(" WHERE {} IN ({})", query.model.primary_key[0], subquery)
+        } else {
+            format!(" WHERE ({}) IN ({})", query.model.primary_key.join(","), subquery)
+        }
+    } else {
+        let mut sql = " WHERE ".to_string();
+        build_filter(&mut Scope::new(query.model, false), &mut sql, params, filter);
+        sql
+    }
+}
+
+fn build_update_all<'a>(query: &'a Query, attributes: &'a Attributes) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let mut names: Vec<&String> = attributes.keys().collect();
+    names.sort();
+
+    let assignments: Vec<String> = names.into_iter().map(|name| {
+        params.push(&attributes[name]);
+        format!("{}=${}", name, params.len())
+    }).collect();
+
+    let where_sql = build_where(query, &mut params);
+    (format!("UPDATE {} SET {}{}", query.model.ty, assignments.join(","), where_sql), params)
+}
+
+fn build_delete_all<'a>(query: &'a Query) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let where_sql = build_where(query, &mut params);
+    (format!("DELETE FROM {}{}", query.model.ty, where_sql), params)
+}
+
+// Postgres takes at most 65535 parameters per statement.
+const MAX_PARAMS: usize = 65535;
+
+// The number of rows of `columns` columns which fit in one statement.
+fn chunk_size(columns: usize) -> usize {
+    cmp::max(1, MAX_PARAMS / cmp::max(1, columns))
+}
+
+// The columns set by any of the rows, the primary key (taking its default) if none is.
+fn columns_of<'a>(model: Model, rows: &'a [Attributes]) -> Vec<&'a str> {
+    let columns: BTreeSet<&str> = rows.iter().flat_map(|row| row.keys().map(|name| name.as_str())).collect();
+    if columns.len() > 0 {
+        columns.into_iter().collect()
+    } else {
+        model.primary_key.iter().map(|name| &name[..]).collect()
+    }
+}
+
+// Rows may set different columns, the missing ones take their default values, as does a null primary
+// key so that it can be generated.
+fn build_values<'a>(model: Model, columns: &[&str], rows: &'a [Attributes], params: &mut Vec<&'a Attribute>) -> String {
+    let values: Vec<String> = rows.iter().map(|row| {
+        let values: Vec<String> = columns.iter().map(|name| match row.get(*name) {
+            Some(attribute) if !(model.id_column() == Some(*name) && attribute.is_null()) => {
+                params.push(attribute);
+                format!("${}", params.len())
+            },
+            _ => "DEFAULT".to_string(),
+        }).collect();
+        format!("({})", values.join(","))
+    }).collect();
+    values.join(",")
+}
+
+// One statement per chunk of rows small enough for the parameter limit.
+fn build_insert_all<'a>(model: Model, rows: &'a [Attributes]) -> Vec<(String, Vec<&'a Attribute>)> {
+    let columns = columns_of(model, rows);
+    rows.chunks(chunk_size(columns.len())).map(|rows| {
+        let mut params = vec![];
+        let values = build_values(model, &columns, rows, &mut params);
+        (format!("INSERT INTO {} ({}) VALUES {} RETURNING *", model.ty, columns.join(","), values), params)
+    }).collect()
+}
+
+fn build_upsert<'a>(model: Model, rows: &'a [Attributes], key: &[&str], update: &[&str]) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let columns = columns_of(model, rows);
+    let values = build_values(model, &columns, rows, &mut params);
+
+    let action = if update.len() > 0 {
+        let assignments: Vec<String> = update.iter().map(|name| format!("{}=EXCLUDED.{}", name, name)).collect();
+        format!("DO UPDATE SET {}", assignments.join(","))
+    } else {
+        "DO NOTHING".to_string()
+    };
+
+    let sql = format!("INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) {} RETURNING *",
+                      model.ty, columns.join(","), values, key.join(","), action);
+    (sql, params)
+}
+
+// Encodes a row in the text format of `COPY`.
+fn copy_row(columns: &[&str], row: &Attributes) -> String {
+    let values: Vec<String> = columns.iter().map(|name| {
+        let value = match row.get(*name) {
+            Some(&Attribute::Bool(Some(value))) => (if value { "t" } else { "f" }).to_string(),
+            Some(&Attribute::I8(Some(value))) => value.to_string(),
+            Some(&Attribute::I16(Some(value))) => value.to_string(),
+            Some(&Attribute::I32(Some(value))) => value.to_string(),
+            Some(&Attribute::I64(Some(value))) => value.to_string(),
+            Some(&Attribute::F32(Some(value))) if !value.is_finite() => copy_non_finite(value as f64),
+            Some(&Attribute::F64(Some(value))) if !value.is_finite() => copy_non_finite(value),
+            Some(&Attribute::F32(Some(value))) => value.to_string(),
+            Some(&Attribute::F64(Some(value))) => value.to_string(),
+            Some(&Attribute::String(Some(ref value))) => value.clone(),
+            _ => return "\\N".to_string(),
+        };
+        value.replace("\\", "\\\\").replace("\t", "\\t").replace("\n", "\\n").replace("\r", "\\r")
+    }).collect();
+    format!("{}\n", values.join("\t"))
+}
+
+// Rust writes these as `NaN`, `inf` and `-inf`, Postgres only reads the first.
+fn copy_non_finite(value: f64) -> String {
+    if value.is_nan() {
+        "NaN"
+    } else if value > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }.to_string()
+}
+
+fn build_delete<'a>(model: Model, key: &'a [Attribute]) -> (String, Vec<&'a Attribute>) {
+    let mut params = vec![];
+    let condition = build_key_condition(model, key, &mut params);
+    (format!("DELETE FROM {} WHERE {}", model.ty, condition), params)
+}
+
 impl Adapter for PostgresAdapter {
-    fn query(&self, query: &Query) -> Option<RecordSet> {
+    fn query_with<S: Serializer>(&self, query: &Query, serializer: &S) -> error::Result<RecordSet> {
         let (sql, params) = build_sql(query);
-        let many_attributes = try_opt!(self.query_raw(sql.as_str(), &params));
-        let records: Vec<Record> = many_attributes.into_iter().map(|attributes| {
-            let mut record = query.model.create();
-            for (name, attribute) in attributes {
-                record.set(&name, attribute);
-            }
-            record
-        }).collect();
+        let many_attributes = try!(self.query_raw(sql.as_str(), &params));
+        let mut records = vec![];
+
+        for attributes in many_attributes {
+            records.push(try!(serializer.extract(query.model, attributes)));
+        }
+
+        Ok(RecordSet::new(records))
+    }
+
+    fn count(&self, query: &Query) -> error::Result<u64> {
+        let (sql, params) = build_count(query);
+        let rows = try!(self.query_raw(sql.as_str(), &params));
+        match rows.into_iter().next().and_then(|mut row| row.remove("count")) {
+            Some(Attribute::I64(Some(count))) => Ok(count as u64),
+            _ => Err(Error::Adapter("COUNT(*) returned no rows".to_string())),
+        }
+    }
+
+    fn insert(&self, model: Model, attributes: &Attributes) -> error::Result<Attributes> {
+        let (sql, params) = build_insert(model, attributes);
+        let rows = try!(self.query_raw(sql.as_str(), &params));
+        rows.into_iter().next().ok_or(Error::RecordNotFound)
+    }
+
+    fn update(&self, model: Model, key: &[Attribute], attributes: &Attributes) -> error::Result<u64> {
+        if attributes.len() == 0 {
+            return Ok(0);
+        }
+
+        let (sql, params) = build_update(model, key, attributes);
+        self.execute_raw(sql.as_str(), &params)
+    }
 
-        Some(RecordSet::new(records))
+    fn delete(&self, model: Model, key: &[Attribute]) -> error::Result<u64> {
+        let (sql, params) = build_delete(model, key);
+        self.execute_raw(sql.as_str(), &params)
+    }
+
+    fn insert_all(&self, model: Model, rows: &[Attributes]) -> error::Result<Vec<Attributes>> {
+        let mut stored = vec![];
+        for (sql, params) in build_insert_all(model, rows) {
+            stored.extend(try!(self.query_raw(sql.as_str(), &params)));
+        }
+        Ok(stored)
+    }
+
+    fn update_all(&self, query: &Query, attributes: &Attributes) -> error::Result<u64> {
+        let (sql, params) = build_update_all(query, attributes);
+        self.execute_raw(sql.as_str(), &params)
+    }
+
+    fn delete_all(&self, query: &Query) -> error::Result<u64> {
+        let (sql, params) = build_delete_all(query);
+        self.execute_raw(sql.as_str(), &params)
+    }
+
+    fn upsert(&self, model: Model, rows: &[Attributes], key: &[&str], update: &[&str]) -> error::Result<Vec<Attributes>> {
+        let mut stored = vec![];
+        for rows in rows.chunks(chunk_size(columns_of(model, rows).len())) {
+            let (sql, params) = build_upsert(model, rows, key, update);
+            stored.extend(try!(self.query_raw(sql.as_str(), &params)));
+        }
+        Ok(stored)
+    }
+
+    fn execute_batch(&self, sql: &str) -> error::Result<()> {
+        Ok(try!(self.conn.batch_execute(sql)))
+    }
+
+    fn transaction<T, F>(&self, f: F) -> error::Result<T> where F: FnOnce(&Self) -> error::Result<T> {
+        let trans = try!(self.conn.transaction());
+        let result = f(self);
+        if result.is_ok() {
+            trans.set_commit();
+        }
+        try!(trans.finish());
+        result
     }
 }
 
@@ -228,10 +766,16 @@ impl Adapter for PostgresAdapter {
 mod tests {
     extern crate env_logger;
 
+    use std::convert::TryFrom;
+    use std::{f32, f64};
+
     use super::*;
     use super::postgres::{Connection, SslMode};
 
-    use query::{Query, SortOrder};
+    use attribute::{Attribute, Attributes};
+    use model::Record;
+    use pagination::Cursor;
+    use query::{Filter, Query, SortOrder};
 
     model! {
         User {
@@ -245,6 +789,63 @@ mod tests {
                 "remember_token": String,
             },
             relationships: {},
+        },
+
+        Item {
+            type: "item",
+            primary_key: "id",
+            attributes: {
+                "name": String,
+                "n": I32,
+            },
+            relationships: {},
+        },
+
+        Customer {
+            type: "shop.customer",
+            primary_key: "id",
+            attributes: {
+                "name": String,
+            },
+            relationships: {
+                "purchases": HasMany<Purchase>,
+            },
+        },
+
+        Purchase {
+            type: "shop.purchase",
+            primary_key: "id",
+            attributes: {
+                "price": F32,
+                "customer_id": I32,
+            },
+            relationships: {
+                "customer": BelongsTo<Customer>,
+            },
+        },
+
+        Reading {
+            type: "metrics.reading",
+            primary_key: ["sensor_id", "taken_at"],
+            attributes: {
+                "sensor_id": I32,
+                "taken_at": I64,
+                "value": F64,
+            },
+            relationships: {
+                "notes": HasMany<Note>,
+            },
+        },
+
+        Note {
+            type: "metrics.note",
+            primary_key: "id",
+            attributes: {
+                "text": String,
+            },
+            relationships: {
+                "reading": BelongsTo<Reading>,
+            },
         }
     }
 
@@ -262,6 +863,255 @@ mod tests {
                                      .order_by("user_id", SortOrder::ASC)
                                      .limit(1)
                                      .get(&adapter);
-        assert!(model.is_some());
+        assert!(model.is_ok());
+    }
+
+    fn items() -> Vec<Record> {
+        vec![(Some("a"), Some(1)), (Some("b"), None), (None, Some(2)), (None, None)].into_iter().enumerate().map(|(i, (name, n))| {
+            let mut item = Item.create();
+            item.id = (i as i32).into();
+            item.set("name", Attribute::String(name.map(|name| name.to_string())));
+            item.set("n", Attribute::I32(n));
+            item
+        }).collect()
+    }
+
+    // The items as a derived table, so filters can be run by Postgres without creating a table
+    fn items_sql(items: &[Record]) -> String {
+        let rows: Vec<String> = items.iter().map(|item| {
+            let name = match *item.get("name").unwrap() {
+                Attribute::String(Some(ref name)) => format!("'{}'", name),
+                _ => "NULL".to_string(),
+            };
+            let n = match *item.get("n").unwrap() {
+                Attribute::I32(Some(n)) => n.to_string(),
+                _ => "NULL".to_string(),
+            };
+            format!("({}::int4, {}::text, {}::int4)", i32::try_from(item.id.clone()).unwrap(), name, n)
+        }).collect();
+        format!("(VALUES {}) AS item(id, name, n)", rows.join(","))
+    }
+
+    #[test]
+    fn test_build_filter() {
+        let one = 1.into();
+        let two = 2.into();
+        let filter = Filter::Or(Box::new(Filter::And(Box::new(Filter::IsNull("name")),
+                                                     Box::new(Filter::Equal("n", &one)))),
+                                Box::new(Filter::In("n", vec![&one, &two])));
+        let mut sql = String::new();
+        let mut params = vec![];
+        build_filter(&mut Scope::plain(), &mut sql, &mut params, &filter);
+        assert_eq!(sql, "((name IS NULL) AND (n=$1)) OR (n IN ($2,$3))");
+        assert_eq!(params, vec![&one, &one, &two]);
+
+        let mut sql = String::new();
+        build_filter(&mut Scope::plain(), &mut sql, &mut params, &Filter::In("n", vec![]));
+        assert_eq!(sql, "FALSE");
+    }
+
+    #[test]
+    fn test_build_sql_with_cursor() {
+        let cursor = Cursor::new(vec![1.into(), 2.into()]);
+        let query = Query::new(&Item).where_("name").is_not_null()
+                                     .order_by("n", SortOrder::DESC)
+                                     .before(cursor)
+                                     .limit(10);
+        let (sql, params) = build_sql(&query);
+        assert_eq!(sql, "SELECT * FROM item WHERE (name IS NOT NULL) AND (((n>$1) OR (n IS NULL)) OR ((n=$2) AND (id<$3))) \
+                         ORDER BY n ASC,id DESC LIMIT 10");
+        assert_eq!(params, vec![&1.into(), &1.into(), &2.into()]);
+    }
+
+    #[test]
+    fn test_build_count() {
+        let one = 1.into();
+        let query = Query::new(&Item).where_("n").eq(&one).order_by("n", SortOrder::ASC).offset(10).limit(10);
+        let (sql, params) = build_count(&query);
+        assert_eq!(sql, "SELECT COUNT(*) AS count FROM item WHERE n=$1");
+        assert_eq!(params, vec![&one]);
+    }
+
+    #[test]
+    fn test_build_sql_with_paths() {
+        let price = 100f32.into();
+        let name = "Alice".to_string().into();
+        let query = Query::new(&Purchase).where_("customer.name").eq(&name)
+                                         .and("price").gt(&price)
+                                         .order_by("customer.name", SortOrder::ASC);
+        let (sql, params) = build_sql(&query);
+        assert_eq!(sql, "SELECT shop.purchase.* FROM shop.purchase \
+                         LEFT JOIN shop.customer AS \"customer\" ON \"customer\".id=shop.purchase.customer_id \
+                         WHERE (\"customer\".name=$1) AND (shop.purchase.price>$2) \
+                         ORDER BY \"customer\".name ASC");
+        assert_eq!(params, vec![&name, &price]);
+
+        let query = Query::new(&Customer).where_("purchases.price").gt(&price)
+                                         .or("purchases.customer.name").eq(&name);
+        let (sql, params) = build_count(&query);
+        assert_eq!(sql, "SELECT COUNT(*) AS count FROM shop.customer WHERE \
+                         (EXISTS (SELECT 1 FROM shop.purchase AS \"purchases\" \
+                         WHERE \"purchases\".customer_id=shop.customer.id AND \"purchases\".price>$1)) OR \
+                         (EXISTS (SELECT 1 FROM shop.purchase AS \"purchases\" \
+                         LEFT JOIN shop.customer AS \"purchases.customer\" ON \"purchases.customer\".id=\"purchases\".customer_id \
+                         WHERE \"purchases\".customer_id=shop.customer.id AND \"purchases.customer\".name=$2))");
+        assert_eq!(params, vec![&price, &name]);
+    }
+
+    #[test]
+    fn test_build_sql_with_subqueries() {
+        let name = "Alice".to_string().into();
+        let price = 100f32.into();
+        let limit = 1000f32.into();
+        let expensive = Query::new(&Purchase).select(vec!["customer_id"]).where_("price").gt(&price);
+        let returned = Query::new(&Purchase).where_("customer_id").eq_outer("id")
+                                            .and("price").lt(&limit)
+                                            .and_exists(Query::new(&Purchase).where_("id").eq_outer("id"));
+        let query = Query::new(&Customer).where_("name").eq(&name)
+                                         .and("id").in_query(expensive)
+                                         .or_exists(returned);
+        let (sql, params) = build_sql(&query);
+        assert_eq!(sql, "SELECT * FROM shop.customer WHERE \
+                         ((name=$1) AND (id IN (SELECT \"s1\".customer_id FROM shop.purchase AS \"s1\" WHERE \"s1\".price>$2))) OR \
+                         (EXISTS (SELECT 1 FROM shop.purchase AS \"s1\" WHERE \
+                         ((\"s1\".customer_id=shop.customer.id) AND (\"s1\".price<$3)) AND \
+                         (EXISTS (SELECT 1 FROM shop.purchase AS \"s2\" WHERE \"s2\".id=\"s1\".id))))");
+        assert_eq!(params, vec![&name, &price, &limit]);
+    }
+
+    #[test]
+    fn test_build_update_and_delete_all() {
+        let price = 100f32.into();
+        let mut attributes = Attributes::new();
+        attributes.insert("price".to_string(), 0f32.into());
+        let query = Query::new(&Purchase).where_("price").gt(&price);
+        let (sql, params) = build_update_all(&query, &attributes);
+        assert_eq!(sql, "UPDATE shop.purchase SET price=$1 WHERE price>$2");
+        assert_eq!(params, vec![&0f32.into(), &price]);
+
+        let name = "Alice".to_string().into();
+        let query = Query::new(&Purchase).where_("customer.name").eq(&name);
+        let (sql, params) = build_delete_all(&query);
+        assert_eq!(sql, "DELETE FROM shop.purchase WHERE id IN (SELECT shop.purchase.id FROM shop.purchase \
+                         LEFT JOIN shop.customer AS \"customer\" ON \"customer\".id=shop.purchase.customer_id \
+                         WHERE \"customer\".name=$1)");
+        assert_eq!(params, vec![&name]);
+
+        let query = Query::new(&Purchase).unfiltered();
+        let (sql, params) = build_delete_all(&query);
+        assert_eq!(sql, "DELETE FROM shop.purchase");
+        assert_eq!(params.len(), 0);
+    }
+
+    #[test]
+    fn test_build_composite_key() {
+        let key = vec![1.into(), 100i64.into()];
+        let mut attributes = Attributes::new();
+        attributes.insert("value".to_string(), 0f64.into());
+        let (sql, params) = build_update(&Reading, &key, &attributes);
+        assert_eq!(sql, "UPDATE metrics.reading SET value=$1 WHERE sensor_id=$2 AND taken_at=$3");
+        assert_eq!(params, vec![&0f64.into(), &key[0], &key[1]]);
+
+        let (sql, params) = build_delete(&Reading, &key);
+        assert_eq!(sql, "DELETE FROM metrics.reading WHERE sensor_id=$1 AND taken_at=$2");
+        assert_eq!(params, vec![&key[0], &key[1]]);
+
+        let value = 1f64.into();
+        let query = Query::new(&Note).where_("reading.value").gt(&value);
+        let (sql, _) = build_sql(&query);
+        assert_eq!(sql, "SELECT metrics.note.* FROM metrics.note \
+                         LEFT JOIN metrics.reading AS \"reading\" ON \"reading\".sensor_id=metrics.note.reading_sensor_id \
+                         AND \"reading\".taken_at=metrics.note.reading_taken_at WHERE \"reading\".value>$1");
+
+        let text = "outlier".to_string().into();
+        let query = Query::new(&Reading).where_("notes.text").eq(&text);
+        let (sql, _) = build_delete_all(&query);
+        assert_eq!(sql, "DELETE FROM metrics.reading WHERE (sensor_id,taken_at) IN \
+                         (SELECT metrics.reading.sensor_id,metrics.reading.taken_at FROM metrics.reading \
+                         WHERE EXISTS (SELECT 1 FROM metrics.note AS \"notes\" \
+                         WHERE \"notes\".reading_sensor_id=metrics.reading.sensor_id \
+                         AND \"notes\".reading_taken_at=metrics.reading.taken_at AND \"notes\".text=$1))");
+    }
+
+    #[test]
+    fn test_build_insert_all() {
+        let rows: Vec<Attributes> = (0..MAX_PARAMS as i32 + 1).map(|n| {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), n.into());
+            row
+        }).collect();
+        let statements = build_insert_all(&Item, &rows);
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[1], ("INSERT INTO item (n) VALUES ($1) RETURNING *".to_string(), vec![&rows[MAX_PARAMS]["n"]]));
+
+        let statements = build_insert_all(&Item, &rows[..2]);
+        assert_eq!(statements[0].0, "INSERT INTO item (n) VALUES ($1),($2) RETURNING *");
+        let empty = vec![Attributes::new(), Attributes::new()];
+        assert_eq!(build_insert_all(&Item, &empty)[0].0, "INSERT INTO item (id) VALUES (DEFAULT),(DEFAULT) RETURNING *");
+
+        let mut row = Attributes::new();
+        row.insert("id".to_string(), Attribute::I32(None));
+        row.insert("name".to_string(), "a\tb\\".to_string().into());
+        assert_eq!(copy_row(&["id", "n", "name"], &row), "\\N\t\\N\ta\\tb\\\\\n");
+
+        let floats: Vec<Attribute> = vec![f32::NAN.into(), f64::INFINITY.into(), f64::NEG_INFINITY.into(), 1.5f64.into()];
+        let row: Attributes = ["a", "b", "c", "d"].iter().map(|name| name.to_string()).zip(floats).collect();
+        assert_eq!(copy_row(&["a", "b", "c", "d"], &row), "NaN\tInfinity\t-Infinity\t1.5\n");
+    }
+
+    #[test]
+    fn test_build_upsert() {
+        let mut rows = vec![Attributes::new(), Attributes::new()];
+        rows[0].insert("id".to_string(), 1.into());
+        rows[0].insert("name".to_string(), "a".to_string().into());
+        rows[1].insert("id".to_string(), Attribute::I32(None));
+        rows[1].insert("n".to_string(), 2.into());
+        let (sql, params) = build_upsert(&Item, &rows, &["id"], &["n", "name"]);
+        assert_eq!(sql, "INSERT INTO item (id,n,name) VALUES ($1,DEFAULT,$2),(DEFAULT,$3,DEFAULT) \
+                         ON CONFLICT (id) DO UPDATE SET n=EXCLUDED.n,name=EXCLUDED.name RETURNING *");
+        assert_eq!(params, vec![&1.into(), &"a".to_string().into(), &2.into()]);
+
+        let (sql, _) = build_upsert(&Item, &rows[..1], &["name"], &[]);
+        assert_eq!(sql, "INSERT INTO item (id,name) VALUES ($1,$2) ON CONFLICT (name) DO NOTHING RETURNING *");
+    }
+
+    #[test]
+    fn test_filter_matches_postgres() {
+        let conn = Connection::connect(DB_CONNECTION_URL, &SslMode::None).unwrap();
+        let adapter = PostgresAdapter::new(conn);
+
+        let a = "a".to_string().into();
+        let one = 1.into();
+        let null = Attribute::I32(None);
+        let unknown = || Box::new(Filter::Equal("n", &null));
+        let filters = vec![
+            Filter::IsNull("name"),
+            Filter::IsNotNull("n"),
+            Filter::Equal("name", &a),
+            Filter::Equal("n", &one),
+            Filter::Equal("n", &null),
+            Filter::In("n", vec![&one, &null]),
+            Filter::In("n", vec![]),
+            Filter::And(Box::new(Filter::IsNull("name")), Box::new(Filter::IsNotNull("n"))),
+            Filter::And(unknown(), Box::new(Filter::IsNull("n"))),
+            Filter::Or(Box::new(Filter::Equal("name", &a)), Box::new(Filter::IsNull("n"))),
+            Filter::Or(unknown(), Box::new(Filter::IsNotNull("name"))),
+            Filter::GreaterThan("n", &one),
+            Filter::LessThan("name", &a),
+            Filter::LessThan("n", &null),
+        ];
+
+        let items = items();
+        for filter in &filters {
+            let mut sql = format!("SELECT id FROM {} WHERE ", items_sql(&items));
+            let mut params = vec![];
+            build_filter(&mut Scope::plain(), &mut sql, &mut params, filter);
+            let mut expected: Vec<Attribute> = adapter.query_raw(&sql, &params).unwrap()
+                                                      .into_iter().map(|row| row["id"].clone()).collect();
+            expected.sort_by(|a, b| a.compare(b).unwrap());
+
+            let actual: Vec<Attribute> = items.iter().filter(|item| filter.matches(item)).map(|item| item.id.clone()).collect();
+            assert_eq!(actual, expected, "{}", sql);
+        }
     }
 }
@@ -0,0 +1,491 @@
+use std::fmt;
+
+use super::postgres_adapter::PostgresAdapter;
+use attribute::{Attribute, AttributeType, Attributes};
+use error::{Error, Result};
+use model::Model;
+use validation::Validation;
+
+/// A column as described by `information_schema.columns`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    /// The name of the column's type, like `int4` or `varchar`.
+    pub udt_name: String,
+    /// The SQL type of the column, `USER-DEFINED` for enums and other custom types.
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+impl Column {
+    /// The type the column is read as, if it can be read at all.
+    pub fn attribute_type(&self) -> Option<AttributeType> {
+        match self.udt_name.as_str() {
+            "bool" => Some(AttributeType::Bool),
+            "char" => Some(AttributeType::I8),
+            "int2" => Some(AttributeType::I16),
+            "int4" => Some(AttributeType::I32),
+            "int8" => Some(AttributeType::I64),
+            "float4" => Some(AttributeType::F32),
+            "float8" => Some(AttributeType::F64),
+            "varchar" | "text" | "bpchar" | "name" => Some(AttributeType::String),
+            // Enums are read as strings
+            _ if self.data_type == "USER-DEFINED" => Some(AttributeType::String),
+            _ => None,
+        }
+    }
+}
+
+/// A single column foreign key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForeignKey {
+    pub column: String,
+    /// The referenced table, qualified by its schema unless it is `public`.
+    pub table: String,
+    pub references: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub primary_key: Vec<String>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    /// The table name as used in `ModelDef.ty`.
+    pub fn ty(&self) -> String {
+        qualify(&self.schema, &self.name)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+/// A difference between a `ModelDef` and its table.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Drift {
+    MissingTable(String),
+    /// A declared attribute without a column.
+    MissingColumn(String),
+    /// A column which isn't declared as an attribute, reading it fails. This includes the foreign
+    /// keys of `BelongsTo` relationships, which need to be declared.
+    ExtraColumn(String),
+    /// A column which can't be read as its declared type: `(name, declared, udt_name)`.
+    TypeMismatch(String, AttributeType, String),
+    /// A `NOT NULL` column, which new records leave null unless they are given a value: it has
+    /// neither a default nor a `Presence` validation.
+    NotNull(String),
+    /// The declared primary key and the actual one.
+    WrongPrimaryKey(Vec<String>, Vec<String>),
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Drift::MissingTable(ref table) => write!(f, "missing table `{}`", table),
+            Drift::MissingColumn(ref name) => write!(f, "missing column `{}`", name),
+            Drift::ExtraColumn(ref name) => write!(f, "undeclared column `{}`", name),
+            Drift::TypeMismatch(ref name, ref declared, ref found) =>
+                write!(f, "column `{}` is declared {:?}, found {}", name, declared, found),
+            Drift::NotNull(ref name) => write!(f, "column `{}` is not nullable", name),
+            Drift::WrongPrimaryKey(ref declared, ref found) =>
+                write!(f, "primary key is declared `{}`, found `{}`", declared.join(","), found.join(",")),
+        }
+    }
+}
+
+impl PostgresAdapter {
+    /// Reads a table from `information_schema` and `pg_catalog`, `ty` is a table name optionally
+    /// qualified by its schema, as in `ModelDef.ty`.
+    pub fn table(&self, ty: &str) -> Result<Option<Table>> {
+        let (schema_name, table_name) = split(ty);
+        let schema: Attribute = schema_name.to_string().into();
+        let name: Attribute = table_name.to_string().into();
+
+        let rows = try!(self.query_raw("SELECT column_name::text AS name, udt_name::text, data_type::text, \
+                                                   is_nullable::text AS nullable \
+                                            FROM information_schema.columns \
+                                            WHERE table_schema=$1 AND table_name=$2 ORDER BY ordinal_position",
+                                           &[&schema, &name]));
+        if rows.len() == 0 {
+            return Ok(None);
+        }
+        let mut columns = vec![];
+        for row in &rows {
+            columns.push(Column {
+                name: try!(text(row, "name")),
+                udt_name: try!(text(row, "udt_name")),
+                data_type: try!(text(row, "data_type")),
+                nullable: try!(text(row, "nullable")) == "YES",
+            });
+        }
+
+        let rows = try!(self.query_raw("SELECT kcu.column_name::text AS name \
+                                            FROM information_schema.table_constraints tc \
+                                            JOIN information_schema.key_column_usage kcu \
+                                            ON kcu.constraint_name=tc.constraint_name AND kcu.table_schema=tc.table_schema \
+                                            AND kcu.table_name=tc.table_name \
+                                            WHERE tc.constraint_type='PRIMARY KEY' AND tc.table_schema=$1 AND tc.table_name=$2 \
+                                            ORDER BY kcu.ordinal_position",
+                                           &[&schema, &name]));
+        let mut primary_key = vec![];
+        for row in &rows {
+            primary_key.push(try!(text(row, "name")));
+        }
+
+        // Only single column foreign keys are reported
+        let rows = try!(self.query_raw("SELECT a.attname::text AS column, fn.nspname::text AS schema, \
+                                                   fc.relname::text AS table, fa.attname::text AS references \
+                                            FROM pg_catalog.pg_constraint c \
+                                            JOIN pg_catalog.pg_class cl ON cl.oid=c.conrelid \
+                                            JOIN pg_catalog.pg_namespace n ON n.oid=cl.relnamespace \
+                                            JOIN pg_catalog.pg_class fc ON fc.oid=c.confrelid \
+                                            JOIN pg_catalog.pg_namespace fn ON fn.oid=fc.relnamespace \
+                                            JOIN pg_catalog.pg_attribute a ON a.attrelid=c.conrelid AND a.attnum=c.conkey[1] \
+                                            JOIN pg_catalog.pg_attribute fa ON fa.attrelid=c.confrelid AND fa.attnum=c.confkey[1] \
+                                            WHERE c.contype='f' AND array_length(c.conkey, 1)=1 AND n.nspname=$1 AND cl.relname=$2 \
+                                            ORDER BY a.attnum",
+                                           &[&schema, &name]));
+        let mut foreign_keys = vec![];
+        for row in &rows {
+            foreign_keys.push(ForeignKey {
+                column: try!(text(row, "column")),
+                table: qualify(&try!(text(row, "schema")), &try!(text(row, "table"))),
+                references: try!(text(row, "references")),
+            });
+        }
+
+        Ok(Some(Table {
+            schema: schema_name.to_string(),
+            name: table_name.to_string(),
+            columns: columns,
+            primary_key: primary_key,
+            foreign_keys: foreign_keys,
+        }))
+    }
+
+    /// Reads every table of a schema.
+    pub fn tables(&self, schema: &str) -> Result<Vec<Table>> {
+        let rows = try!(self.query_raw("SELECT table_name::text AS name FROM information_schema.tables \
+                                            WHERE table_schema=$1 AND table_type='BASE TABLE' ORDER BY table_name",
+                                           &[&schema.to_string().into()]));
+        let mut tables = vec![];
+        for row in &rows {
+            let ty = qualify(schema, &try!(text(row, "name")));
+            if let Some(table) = try!(self.table(&ty)) {
+                tables.push(table);
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Compares a model with its table, see `drift`.
+    pub fn check_model(&self, model: Model) -> Result<Vec<Drift>> {
+        let table = try!(self.table(&model.ty));
+        Ok(drift(model, table.as_ref()))
+    }
+}
+
+/// Lists the differences between a model and its table, or reports the table missing. Columns
+/// which read into the declared type without loss, like an `int4` column declared `I64`, are
+/// accepted.
+pub fn drift(model: Model, table: Option<&Table>) -> Vec<Drift> {
+    let table = match table {
+        Some(table) => table,
+        None => return vec![Drift::MissingTable(model.ty.to_string())],
+    };
+    let mut drift = vec![];
+
+    if table.primary_key != model.primary_key {
+        drift.push(Drift::WrongPrimaryKey(model.primary_key.clone(), table.primary_key.clone()));
+    }
+
+    // The columns of a composite key are declared as attributes
+    let mut declared: Vec<(String, Option<AttributeType>)> = model.id_column().map(|name| (name.to_string(), model.id_type())).into_iter().collect();
+    let mut attributes: Vec<(&String, &AttributeType)> = model.attributes.iter().collect();
+    attributes.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, &ty) in attributes {
+        if model.id_column() != Some(name) {
+            declared.push((name.to_string(), Some(ty)));
+        }
+    }
+
+    for &(ref name, ty) in &declared {
+        let column = match table.column(name) {
+            Some(column) => column,
+            None => {
+                drift.push(Drift::MissingColumn(name.clone()));
+                continue;
+            },
+        };
+        if let Some(ty) = ty {
+            let readable = column.attribute_type().and_then(|found| found.to_attribute().coerce(ty)).is_some();
+            if !readable {
+                drift.push(Drift::TypeMismatch(name.clone(), ty, column.udt_name.clone()));
+            }
+        }
+        let filled = model.is_primary_key(name) || model.defaults.contains_key(name) || required(model, name);
+        if !column.nullable && !filled {
+            drift.push(Drift::NotNull(name.clone()));
+        }
+    }
+
+    for column in &table.columns {
+        if !declared.iter().any(|&(ref name, _)| *name == column.name) {
+            drift.push(Drift::ExtraColumn(column.name.clone()));
+        }
+    }
+
+    drift
+}
+
+/// Generates a `model!` declaration for the tables. Foreign keys named `<name>_id` which point at
+/// one of the tables become a `BelongsTo` relationship named `<name>`, along with a `HasMany` one
+/// the other way, named after the referencing table. What can't be declared is left as comments.
+pub fn model_source(tables: &[Table]) -> String {
+    // Models are named after their table, or after their schema too when that is ambiguous
+    let names: Vec<String> = tables.iter().map(|table| {
+        if tables.iter().filter(|other| other.name == table.name).count() > 1 {
+            format!("{}{}", camel_case(&table.schema), camel_case(&table.name))
+        } else {
+            camel_case(&table.name)
+        }
+    }).collect();
+    let model_of = |ty: &str| tables.iter().position(|table| table.ty() == ty).map(|i| names[i].as_str());
+
+    let models: Vec<String> = tables.iter().zip(names.iter()).map(|(table, name)| {
+        let mut lines = vec![format!("    {} {{", name), format!("        type: \"{}\",", table.ty())];
+
+        // The columns of a composite key are declared as attributes, so is a single key column
+        // unless it is an `I32`, which is the type of undeclared keys
+        let id_column = match table.primary_key.len() {
+            1 => {
+                lines.push(format!("        primary_key: \"{}\",", table.primary_key[0]));
+                Some(&table.primary_key[0])
+            },
+            0 => {
+                lines.push("        // no primary key".to_string());
+                lines.push("        primary_key: \"id\",".to_string());
+                None
+            },
+            _ => {
+                let columns: Vec<String> = table.primary_key.iter().map(|name| format!("\"{}\"", name)).collect();
+                lines.push(format!("        primary_key: [{}],", columns.join(", ")));
+                None
+            },
+        };
+
+        lines.push("        attributes: {".to_string());
+        for column in table.columns.iter().filter(|column| Some(&column.name) != id_column || column.attribute_type() != Some(AttributeType::I32)) {
+            match column.attribute_type() {
+                Some(ty) => lines.push(format!("            \"{}\": {:?},", column.name, ty)),
+                None => lines.push(format!("            // \"{}\": unsupported type {}", column.name, column.udt_name)),
+            }
+        }
+        lines.push("        },".to_string());
+
+        lines.push("        relationships: {".to_string());
+        for foreign_key in &table.foreign_keys {
+            let relationship = if foreign_key.column.ends_with("_id") {
+                Some(&foreign_key.column[..foreign_key.column.len() - 3])
+            } else {
+                None
+            };
+            match (relationship, model_of(&foreign_key.table)) {
+                (Some(relationship), Some(target)) => lines.push(format!("            \"{}\": BelongsTo<{}>,", relationship, target)),
+                _ => lines.push(format!("            // \"{}\" references {} ({})", foreign_key.column, foreign_key.table, foreign_key.references)),
+            }
+        }
+        for other in tables {
+            let foreign_keys: Vec<&ForeignKey> = other.foreign_keys.iter().filter(|foreign_key| {
+                foreign_key.table == table.ty() && foreign_key.column.ends_with("_id")
+            }).collect();
+            match foreign_keys.len() {
+                0 => {},
+                // `ModelDef::foreign_key_in` can't tell several foreign keys apart
+                1 => lines.push(format!("            \"{}\": HasMany<{}>,", plural(&other.name), model_of(&other.ty()).unwrap())),
+                _ => lines.push(format!("            // \"{}\": several foreign keys reference this table", plural(&other.name))),
+            }
+        }
+        lines.push("        },".to_string());
+
+        lines.push("    }".to_string());
+        lines.join("\n")
+    }).collect();
+
+    format!("model! {{\n{}\n}}\n", models.join(",\n\n"))
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_').map(|word| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }).collect()
+}
+
+fn plural(name: &str) -> String {
+    if name.ends_with('s') {
+        name.to_string()
+    } else {
+        format!("{}s", name)
+    }
+}
+
+fn required(model: Model, name: &str) -> bool {
+    model.validations.get(name).map_or(false, |validations| validations.iter().any(|validation| match *validation {
+        Validation::Presence => true,
+        _ => false,
+    }))
+}
+
+fn split(ty: &str) -> (&str, &str) {
+    match ty.find('.') {
+        Some(i) => (&ty[..i], &ty[i + 1..]),
+        None => ("public", ty),
+    }
+}
+
+fn qualify(schema: &str, name: &str) -> String {
+    if schema == "public" {
+        name.to_string()
+    } else {
+        format!("{}.{}", schema, name)
+    }
+}
+
+fn text(row: &Attributes, name: &str) -> Result<String> {
+    match row.get(name) {
+        Some(&Attribute::String(Some(ref value))) => Ok(value.clone()),
+        Some(attribute) => Err(Error::TypeMismatch(name.to_string(), AttributeType::String, attribute.ty())),
+        None => Err(Error::UnknownAttribute(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attribute::AttributeType;
+
+    model! {
+        Customer {
+            type: "shop.customer",
+            attributes: {
+                "name": String,
+                "visits": I64,
+            },
+            relationships: {
+                "orders": HasMany<Purchase>,
+            },
+            defaults: {
+                "visits": 0i64,
+            },
+        },
+
+        Purchase {
+            type: "shop.purchase",
+            attributes: {
+                "price": F32,
+            },
+            relationships: {
+                "customer": BelongsTo<Customer>,
+            },
+            validations: {
+                "price": [Presence],
+            },
+        }
+    }
+
+    fn column(name: &str, udt_name: &str, nullable: bool) -> Column {
+        Column {
+            name: name.to_string(),
+            udt_name: udt_name.to_string(),
+            data_type: String::new(),
+            nullable: nullable,
+        }
+    }
+
+    fn table(schema: &str, name: &str, columns: Vec<Column>, foreign_keys: Vec<ForeignKey>) -> Table {
+        Table {
+            schema: schema.to_string(),
+            name: name.to_string(),
+            columns: columns,
+            primary_key: vec!["id".to_string()],
+            foreign_keys: foreign_keys,
+        }
+    }
+
+    #[test]
+    fn test_model_source() {
+        let tables = vec![
+            table("shop", "customer", vec![column("id", "int4", false), column("full_name", "text", true),
+                                           column("joined", "timestamptz", true)], vec![]),
+            table("shop", "order_item", vec![column("id", "int4", false), column("customer_id", "int4", true)], vec![
+                ForeignKey { column: "customer_id".to_string(), table: "shop.customer".to_string(), references: "id".to_string() },
+            ]),
+        ];
+        assert_eq!(model_source(&tables), "\
+model! {
+    Customer {
+        type: \"shop.customer\",
+        primary_key: \"id\",
+        attributes: {
+            \"full_name\": String,
+            // \"joined\": unsupported type timestamptz
+        },
+        relationships: {
+            \"order_items\": HasMany<OrderItem>,
+        },
+    },
+
+    OrderItem {
+        type: \"shop.order_item\",
+        primary_key: \"id\",
+        attributes: {
+            \"customer_id\": I32,
+        },
+        relationships: {
+            \"customer\": BelongsTo<Customer>,
+        },
+    }
+}
+");
+    }
+
+    #[test]
+    fn test_drift() {
+        let table = Table {
+            schema: "shop".to_string(),
+            name: "customer".to_string(),
+            columns: vec![column("id", "int4", false), column("name", "text", false), column("visits", "int4", false)],
+            primary_key: vec!["id".to_string()],
+            foreign_keys: vec![],
+        };
+        assert_eq!(table.ty(), "shop.customer");
+        assert_eq!(drift(&Customer, Some(&table)), vec![Drift::NotNull("name".to_string())]);
+        assert_eq!(drift(&Customer, None), vec![Drift::MissingTable("shop.customer".to_string())]);
+
+        let table = Table {
+            schema: "shop".to_string(),
+            name: "purchase".to_string(),
+            columns: vec![column("code", "text", false), column("price", "numeric", false), column("customer_id", "int4", true),
+                          column("coupon", "text", true)],
+            primary_key: vec!["code".to_string()],
+            foreign_keys: vec![],
+        };
+        assert_eq!(drift(&Purchase, Some(&table)), vec![
+            Drift::WrongPrimaryKey(vec!["id".to_string()], vec!["code".to_string()]),
+            Drift::MissingColumn("id".to_string()),
+            Drift::TypeMismatch("price".to_string(), AttributeType::F32, "numeric".to_string()),
+            Drift::ExtraColumn("code".to_string()),
+            Drift::ExtraColumn("customer_id".to_string()),
+            Drift::ExtraColumn("coupon".to_string()),
+        ]);
+    }
+}
@@ -1,13 +1,29 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 
-pub type AttributeTypes = HashMap<&'static str, AttributeType>;
+pub type AttributeTypes = HashMap<String, AttributeType>;
 pub type Attributes = HashMap<String, Attribute>;
 
+/// Conversion from a borrowed `Attribute`, used by the typed accessors of `Record`.
+pub trait FromAttribute: Sized {
+    /// Returns `Ok(None)` for a null value, or the expected `AttributeType` if the attribute is of
+    /// another type.
+    fn from_attribute(attribute: &Attribute) -> Result<Option<Self>, AttributeType>;
+}
+
+impl<T: FromAttribute> FromAttribute for Option<T> {
+    fn from_attribute(attribute: &Attribute) -> Result<Option<Option<T>>, AttributeType> {
+        T::from_attribute(attribute).map(Some)
+    }
+}
+
 macro_rules! attribute {
     (
         $($attribute_type:ident<$attribute:ty>,)*
     ) => {
-        #[derive(Debug)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub enum AttributeType {
             $($attribute_type,)*
         }
@@ -20,19 +36,68 @@ macro_rules! attribute {
                     )*
                 }
             }
+
+            /// The type named like its variant, e.g. `"I32"`.
+            pub fn from_name(name: &str) -> Option<AttributeType> {
+                match name {
+                    $(
+                        stringify!($attribute_type) => Some(AttributeType::$attribute_type),
+                    )*
+                    _ => None,
+                }
+            }
         }
 
-        #[derive(Clone, Debug, PartialEq)]
+        #[derive(Clone, Debug)]
         pub enum Attribute {
             $($attribute_type(Option<$attribute>),)*
         }
 
+        impl Attribute {
+            pub fn ty(&self) -> AttributeType {
+                match *self {
+                    $(
+                        Attribute::$attribute_type(_) => AttributeType::$attribute_type,
+                    )*
+                }
+            }
+
+            pub fn is_null(&self) -> bool {
+                match *self {
+                    $(
+                        Attribute::$attribute_type(ref attribute) => attribute.is_none(),
+                    )*
+                }
+            }
+        }
+
         $(
             impl Into<Attribute> for $attribute {
                 fn into(self) -> Attribute {
                     Attribute::$attribute_type(Some(self))
                 }
             }
+
+            impl TryFrom<Attribute> for $attribute {
+                /// The attribute is given back if it is null or of another type.
+                type Error = Attribute;
+
+                fn try_from(attribute: Attribute) -> Result<$attribute, Attribute> {
+                    match attribute {
+                        Attribute::$attribute_type(Some(attribute)) => Ok(attribute),
+                        attribute => Err(attribute),
+                    }
+                }
+            }
+
+            impl FromAttribute for $attribute {
+                fn from_attribute(attribute: &Attribute) -> Result<Option<$attribute>, AttributeType> {
+                    match *attribute {
+                        Attribute::$attribute_type(ref attribute) => Ok(attribute.clone()),
+                        _ => Err(AttributeType::$attribute_type),
+                    }
+                }
+            }
         )*
     }
 }
@@ -47,3 +112,123 @@ attribute! {
     F64<f64>,
     String<String>,
 }
+
+impl<'a> Into<Attribute> for &'a str {
+    fn into(self) -> Attribute {
+        Attribute::String(Some(self.to_string()))
+    }
+}
+
+impl Attribute {
+    /// Converts the attribute to `ty` if that can be done without losing information, e.g. widening
+    /// `I32` to `I64`.
+    pub fn coerce(self, ty: AttributeType) -> Option<Attribute> {
+        if self.ty() == ty {
+            return Some(self);
+        }
+
+        match (self, ty) {
+            (Attribute::I8(a), AttributeType::I16) => Some(Attribute::I16(a.map(|a| a as i16))),
+            (Attribute::I8(a), AttributeType::I32) => Some(Attribute::I32(a.map(|a| a as i32))),
+            (Attribute::I8(a), AttributeType::I64) => Some(Attribute::I64(a.map(|a| a as i64))),
+            (Attribute::I8(a), AttributeType::F32) => Some(Attribute::F32(a.map(|a| a as f32))),
+            (Attribute::I8(a), AttributeType::F64) => Some(Attribute::F64(a.map(|a| a as f64))),
+            (Attribute::I16(a), AttributeType::I32) => Some(Attribute::I32(a.map(|a| a as i32))),
+            (Attribute::I16(a), AttributeType::I64) => Some(Attribute::I64(a.map(|a| a as i64))),
+            (Attribute::I16(a), AttributeType::F32) => Some(Attribute::F32(a.map(|a| a as f32))),
+            (Attribute::I16(a), AttributeType::F64) => Some(Attribute::F64(a.map(|a| a as f64))),
+            (Attribute::I32(a), AttributeType::I64) => Some(Attribute::I64(a.map(|a| a as i64))),
+            (Attribute::I32(a), AttributeType::F64) => Some(Attribute::F64(a.map(|a| a as f64))),
+            (Attribute::F32(a), AttributeType::F64) => Some(Attribute::F64(a.map(|a| a as f64))),
+            _ => None,
+        }
+    }
+}
+
+impl Attribute {
+    /// Orders attributes the way Postgres does by default: nulls sort after any value. Attributes
+    /// of different types are unordered.
+    pub fn compare(&self, other: &Attribute) -> Option<Ordering> {
+        match (self.is_null(), other.is_null()) {
+            (true, true) => return Some(Ordering::Equal),
+            (true, false) => return Some(Ordering::Greater),
+            (false, true) => return Some(Ordering::Less),
+            _ => {},
+        }
+
+        match (self, other) {
+            (&Attribute::Bool(ref a), &Attribute::Bool(ref b)) => a.partial_cmp(b),
+            (&Attribute::I8(ref a), &Attribute::I8(ref b)) => a.partial_cmp(b),
+            (&Attribute::I16(ref a), &Attribute::I16(ref b)) => a.partial_cmp(b),
+            (&Attribute::I32(ref a), &Attribute::I32(ref b)) => a.partial_cmp(b),
+            (&Attribute::I64(ref a), &Attribute::I64(ref b)) => a.partial_cmp(b),
+            (&Attribute::F32(ref a), &Attribute::F32(ref b)) => a.partial_cmp(b),
+            (&Attribute::F64(ref a), &Attribute::F64(ref b)) => a.partial_cmp(b),
+            (&Attribute::String(ref a), &Attribute::String(ref b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+// Attributes are used as record identities (e.g. in the `Store`'s identity map), so they need to be
+// hashable. Floats are compared and hashed by their bit pattern, which is fine for keys: `NaN`
+// equals itself while `0.0` and `-0.0` differ. See `compare` for the ordering of values.
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Attribute) -> bool {
+        match (self, other) {
+            (&Attribute::Bool(ref a), &Attribute::Bool(ref b)) => a == b,
+            (&Attribute::I8(ref a), &Attribute::I8(ref b)) => a == b,
+            (&Attribute::I16(ref a), &Attribute::I16(ref b)) => a == b,
+            (&Attribute::I32(ref a), &Attribute::I32(ref b)) => a == b,
+            (&Attribute::I64(ref a), &Attribute::I64(ref b)) => a == b,
+            (&Attribute::F32(ref a), &Attribute::F32(ref b)) => a.map(f32::to_bits) == b.map(f32::to_bits),
+            (&Attribute::F64(ref a), &Attribute::F64(ref b)) => a.map(f64::to_bits) == b.map(f64::to_bits),
+            (&Attribute::String(ref a), &Attribute::String(ref b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Attribute {}
+
+impl Hash for Attribute {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Attribute::Bool(ref attribute) => { 0u8.hash(state); attribute.hash(state) },
+            Attribute::I8(ref attribute) => { 1u8.hash(state); attribute.hash(state) },
+            Attribute::I16(ref attribute) => { 2u8.hash(state); attribute.hash(state) },
+            Attribute::I32(ref attribute) => { 3u8.hash(state); attribute.hash(state) },
+            Attribute::I64(ref attribute) => { 4u8.hash(state); attribute.hash(state) },
+            Attribute::F32(ref attribute) => {
+                5u8.hash(state);
+                attribute.map(f32::to_bits).hash(state)
+            },
+            Attribute::F64(ref attribute) => {
+                6u8.hash(state);
+                attribute.map(f64::to_bits).hash(state)
+            },
+            Attribute::String(ref attribute) => { 7u8.hash(state); attribute.hash(state) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::f64;
+
+    use super::*;
+
+    #[test]
+    fn test_float_identity() {
+        assert_eq!(Attribute::F64(Some(f64::NAN)), Attribute::F64(Some(f64::NAN)));
+        assert!(Attribute::F64(Some(0.0)) != Attribute::F64(Some(-0.0)));
+        assert!(Attribute::F32(Some(1.0)) != Attribute::F64(Some(1.0)));
+
+        let mut keys = HashSet::new();
+        keys.insert(Attribute::F64(Some(f64::NAN)));
+        keys.insert(Attribute::F64(Some(0.0)));
+        assert!(keys.contains(&Attribute::F64(Some(f64::NAN))));
+        assert!(!keys.contains(&Attribute::F64(Some(-0.0))));
+    }
+}
@@ -0,0 +1,43 @@
+//! Prints the `model!` declarations of the tables in the given Postgres schemas.
+//!
+//! Usage: `stone_data_models <connection url> <schema>...`
+
+extern crate postgres;
+extern crate stone_data;
+
+use std::env;
+use std::io::prelude::*;
+use std::io;
+use std::process;
+
+use postgres::{Connection, SslMode};
+use stone_data::adapter::postgres_adapter::PostgresAdapter;
+use stone_data::adapter::postgres_schema;
+
+fn fail(message: String) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        fail(format!("usage: {} <connection url> <schema>...", args[0]));
+    }
+
+    let conn = match Connection::connect(&args[1][..], &SslMode::None) {
+        Ok(conn) => conn,
+        Err(e) => fail(format!("could not connect: {:?}", e)),
+    };
+    let adapter = PostgresAdapter::new(conn);
+
+    let mut tables = vec![];
+    for schema in &args[2..] {
+        match adapter.tables(schema) {
+            Ok(found) => tables.extend(found),
+            Err(e) => fail(format!("could not read schema `{}`: {}", schema, e)),
+        }
+    }
+
+    print!("{}", postgres_schema::model_source(&tables));
+}
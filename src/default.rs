@@ -0,0 +1,91 @@
+//! Default values of attributes, declared in `model!` and set on the records made by
+//! `ModelDef::create()`:
+//!
+//! ```text
+//! defaults: {
+//!     "status": "draft",
+//!     "stock": 0,
+//!     "created_at": now(),
+//!     "token": uuid(),
+//!     "code": next_code(),
+//! },
+//! ```
+//!
+//! Literals are widened to the declared type where that is lossless, e.g. `0` for an `I64`.
+
+extern crate time;
+
+use std::collections::HashMap;
+
+use attribute::{Attribute, AttributeType};
+use uuid;
+
+pub type Defaults = HashMap<String, DefaultValue>;
+
+#[derive(Debug)]
+pub enum DefaultValue {
+    Value(Attribute),
+    /// The current time, see `now`.
+    Now,
+    /// A random UUID, see `uuid`.
+    Uuid,
+    /// Values returned by the given function. Those which can't be converted to the type of the
+    /// attribute leave it null.
+    Generated(fn() -> Attribute),
+}
+
+impl DefaultValue {
+    /// The type of the values, unknown for generated ones.
+    pub fn ty(&self) -> Option<AttributeType> {
+        match *self {
+            DefaultValue::Value(ref value) => Some(value.ty()),
+            DefaultValue::Now | DefaultValue::Uuid => Some(AttributeType::String),
+            DefaultValue::Generated(_) => None,
+        }
+    }
+
+    /// The value of a new record, converted to `ty`, see `ModelDef::check_defaults`.
+    pub fn value(&self, ty: AttributeType) -> Attribute {
+        let value = match *self {
+            DefaultValue::Value(ref value) => value.clone(),
+            DefaultValue::Now => now(),
+            DefaultValue::Uuid => uuid(),
+            DefaultValue::Generated(generate) => generate(),
+        };
+        value.coerce(ty).unwrap_or_else(|| ty.to_attribute())
+    }
+
+    /// The SQL expression of the default, if the database can compute it.
+    pub fn to_sql(&self) -> Option<String> {
+        match *self {
+            DefaultValue::Value(ref value) => literal(value),
+            DefaultValue::Now => Some("to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"')".to_string()),
+            DefaultValue::Uuid => Some("gen_random_uuid()::text".to_string()),
+            DefaultValue::Generated(_) => None,
+        }
+    }
+}
+
+fn literal(value: &Attribute) -> Option<String> {
+    match *value {
+        Attribute::Bool(Some(value)) => Some(value.to_string()),
+        Attribute::I8(Some(value)) => Some(value.to_string()),
+        Attribute::I16(Some(value)) => Some(value.to_string()),
+        Attribute::I32(Some(value)) => Some(value.to_string()),
+        Attribute::I64(Some(value)) => Some(value.to_string()),
+        Attribute::F32(Some(value)) => Some(value.to_string()),
+        Attribute::F64(Some(value)) => Some(value.to_string()),
+        Attribute::String(Some(ref value)) => Some(format!("'{}'", value.replace("'", "''"))),
+        _ => None,
+    }
+}
+
+/// The current UTC time as an RFC 3339 string, e.g. `"2015-06-01T12:00:00Z"`.
+pub fn now() -> Attribute {
+    time::now_utc().rfc3339().to_string().into()
+}
+
+/// A random UUID string, see `uuid::v4`.
+pub fn uuid() -> Attribute {
+    uuid::v4().into()
+}
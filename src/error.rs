@@ -0,0 +1,88 @@
+use std::error;
+use std::fmt;
+use std::result;
+
+use attribute::AttributeType;
+use validation::Errors;
+
+#[derive(Debug)]
+pub enum Error {
+    RecordNotFound,
+    UnknownAttribute(String),
+    NullAttribute(String),
+    /// An attribute was given a value of the wrong type: `(name, expected, found)`.
+    TypeMismatch(String, AttributeType, AttributeType),
+    UnknownRelationship(String),
+    /// No model of this type is registered, see `Registry`.
+    UnknownModel(String),
+    /// Another model of this type is already registered.
+    DuplicateModel(String),
+    /// The attributes are neither the primary key nor declared unique.
+    UnknownKey(String),
+    /// A dotted path like `"orders.price"` can't be used where it was given.
+    InvalidPath(String),
+    InvalidCursor(String),
+    InvalidParameter(String),
+    /// A record failed its validations, see `Record::validate`.
+    InvalidRecord(Errors),
+    /// An applied migration version which isn't known to the `Migrator`.
+    UnknownMigration(i64),
+    /// A runtime model definition can't be read, see `schema`.
+    InvalidSchema(String),
+    /// A bulk update or delete was given no filter, see `Query::unfiltered`.
+    MissingFilter,
+    /// A filter with subqueries was tested in memory, see `Filter::check_local`.
+    UnsupportedFilter,
+    Adapter(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::RecordNotFound => write!(f, "record not found"),
+            Error::UnknownAttribute(ref name) => write!(f, "unknown attribute `{}`", name),
+            Error::NullAttribute(ref name) => write!(f, "attribute `{}` is null", name),
+            Error::TypeMismatch(ref name, ref expected, ref found) =>
+                write!(f, "attribute `{}` expects {:?}, found {:?}", name, expected, found),
+            Error::UnknownRelationship(ref name) => write!(f, "unknown relationship `{}`", name),
+            Error::UnknownModel(ref ty) => write!(f, "unknown model `{}`", ty),
+            Error::DuplicateModel(ref ty) => write!(f, "model `{}` is already registered", ty),
+            Error::UnknownKey(ref names) => write!(f, "`{}` is not a unique key", names),
+            Error::InvalidPath(ref path) => write!(f, "invalid path `{}`", path),
+            Error::InvalidCursor(ref cursor) => write!(f, "invalid cursor `{}`", cursor),
+            Error::InvalidParameter(ref name) => write!(f, "invalid parameter `{}`", name),
+            Error::InvalidRecord(ref errors) => write!(f, "invalid record: {}", errors),
+            Error::UnknownMigration(version) => write!(f, "unknown migration {}", version),
+            Error::InvalidSchema(ref message) => write!(f, "invalid schema: {}", message),
+            Error::MissingFilter => write!(f, "refusing to update or delete every record without a filter"),
+            Error::UnsupportedFilter => write!(f, "subqueries can't be evaluated in memory"),
+            Error::Adapter(ref message) => write!(f, "adapter error: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::RecordNotFound => "record not found",
+            Error::UnknownAttribute(_) => "unknown attribute",
+            Error::NullAttribute(_) => "null attribute",
+            Error::TypeMismatch(..) => "attribute type mismatch",
+            Error::UnknownRelationship(_) => "unknown relationship",
+            Error::UnknownModel(_) => "unknown model",
+            Error::DuplicateModel(_) => "duplicate model",
+            Error::UnknownKey(_) => "unknown key",
+            Error::InvalidPath(_) => "invalid path",
+            Error::InvalidCursor(_) => "invalid cursor",
+            Error::InvalidParameter(_) => "invalid parameter",
+            Error::InvalidRecord(_) => "invalid record",
+            Error::UnknownMigration(_) => "unknown migration",
+            Error::InvalidSchema(_) => "invalid schema",
+            Error::MissingFilter => "missing filter",
+            Error::UnsupportedFilter => "unsupported filter",
+            Error::Adapter(_) => "adapter error",
+        }
+    }
+}
@@ -1,20 +1,28 @@
 #![feature(convert)]
+#![feature(try_from)]
 
 #[macro_use]
 pub mod macros;
 
 pub mod adapter;
 pub mod attribute;
+pub mod default;
+pub mod error;
+pub mod migration;
 pub mod model;
+pub mod pagination;
 pub mod query;
+pub mod registry;
 pub mod relationship;
-// pub mod store;
+#[cfg(feature="schema")]
+pub mod schema;
+pub mod store;
 pub mod serializer;
+pub mod upsert;
+pub mod uuid;
+pub mod validation;
 // pub mod transform;
 
-// Error
-//     RecordNotFound
-
 //
 // /*
 // pub struct Record<M> {
@@ -2,24 +2,40 @@ macro_rules! model {
     (
         $($T:ident {
             type: $ty:tt,
+            $(key_generation: $generation:ident $(($generator:expr))*,)*
             attributes: {
                 $($attribute_name:tt : $attribute_type:ident,)*
             },
             relationships: {
                 $($relationship_name:tt : $relationship_type:ident<$relationship:ident>,)*
             },
+            $(defaults: {
+                $($defaults:tt)*
+            },)*
+            $(validations: {
+                $($validated:tt : [$($validation:ident $(($($argument:expr),*))*),*],)*
+            },)*
+            $(unique: [$([$($unique:tt),*],)*],)*
         }),*
     ) => {
         model! {
             $($T {
                 type: $ty,
                 primary_key: "id",
+                $(key_generation: $generation $(($generator))*,)*
                 attributes: {
                     $($attribute_name: $attribute_type,)*
                 },
                 relationships: {
                     $($relationship_name: $relationship_type<$relationship>,)*
                 },
+                $(defaults: {
+                    $($defaults)*
+                },)*
+                $(validations: {
+                    $($validated: [$($validation $(($($argument),*))*),*],)*
+                },)*
+                $(unique: [$([$($unique),*],)*],)*
             }),*
         }
     };
@@ -28,23 +44,55 @@ macro_rules! model {
         $($T:ident {
             type: $ty:tt,
             primary_key: $primary_key:tt,
+            $(key_generation: $generation:ident $(($generator:expr))*,)*
             attributes: {
                 $($attribute_name:tt : $attribute_type:ident,)*
             },
             relationships: {
                 $($relationship_name:tt : $relationship_type:ident<$relationship:ident>,)*
             },
+            $(defaults: {
+                $($defaults:tt)*
+            },)*
+            $(validations: {
+                $($validated:tt : [$($validation:ident $(($($argument:expr),*))*),*],)*
+            },)*
+            $(unique: [$([$($unique:tt),*],)*],)*
         }),*
     ) => {
         lazy_static! {
             $(
-                pub static ref $T: $crate::model::ModelDef = $crate::model::model($ty, $primary_key), |m| {
+                pub static ref $T: $crate::model::ModelDef = $crate::model::model($ty, $crate::model::KeyColumns::key_columns(&$primary_key[..])), |m| {
                     $(
-                        m.attributes.insert($attribute_name, $crate::attribute::AttributeType::$attribute_type);
+                        m.key_generation = $crate::model::KeyGeneration::$generation $(($generator))*;
                     )*
 
                     $(
-                        m.relationships.insert($relationship_name, $crate::relationship::RelationshipType::$relationship_type($relationship.get_ref()));
+                        m.attributes.insert($attribute_name.to_string(), $crate::attribute::AttributeType::$attribute_type);
+                    )*
+
+                    $(
+                        m.relationships.insert($relationship_name.to_string(), $crate::relationship::RelationshipType::$relationship_type($relationship.get_ref()));
+                    )*
+
+                    $(
+                        defaults!(m, $($defaults)*);
+                    )*
+                    if let Err(err) = m.check_defaults() {
+                        panic!("{} in the defaults of `{}`", err, $ty);
+                    }
+
+                    $($(
+                        m.validations.insert($validated.to_string(), vec![$(validation!($validation $(($($argument),*))*)),*]);
+                    )*)*
+
+                    $($(
+                        m.unique.push(vec![$($unique.to_string()),*]);
+                    )*)*
+                }, then {
+                    // Relationships refer to the related models before they are initialized
+                    $(
+                        let _ = &*$relationship;
                     )*
                 };
             )*
@@ -60,17 +108,55 @@ macro_rules! model {
     };
 }
 
+// Adds the `DefaultValue`s declared in `model!` to `m`: generator function calls or literals.
+macro_rules! defaults {
+    ($m:ident, ) => ();
+    ($m:ident, $name:tt : now(), $($rest:tt)*) => {
+        $m.defaults.insert($name.to_string(), $crate::default::DefaultValue::Now);
+        defaults!($m, $($rest)*);
+    };
+    ($m:ident, $name:tt : uuid(), $($rest:tt)*) => {
+        $m.defaults.insert($name.to_string(), $crate::default::DefaultValue::Uuid);
+        defaults!($m, $($rest)*);
+    };
+    ($m:ident, $name:tt : $generate:ident(), $($rest:tt)*) => {
+        $m.defaults.insert($name.to_string(), $crate::default::DefaultValue::Generated($generate));
+        defaults!($m, $($rest)*);
+    };
+    ($m:ident, $name:tt : $value:expr, $($rest:tt)*) => {
+        $m.defaults.insert($name.to_string(), $crate::default::DefaultValue::Value(::std::convert::Into::<$crate::attribute::Attribute>::into($value)));
+        defaults!($m, $($rest)*);
+    };
+}
+
+// Builds a `Validation` as declared in `model!`, converting the arguments.
+macro_rules! validation {
+    (Length($min:expr, $max:expr)) => ($crate::validation::Validation::Length($min as usize, $max as usize));
+    (Range($min:expr, $max:expr)) => ($crate::validation::Validation::Range($min as f64, $max as f64));
+    (Format($pattern:expr)) => (match $crate::validation::Validation::format($pattern) {
+        Ok(validation) => validation,
+        Err(err) => panic!("{} of a `Format` validation", err),
+    });
+    (Inclusion($($value:expr),*)) => ($crate::validation::Validation::Inclusion(vec![$(::std::convert::Into::<$crate::attribute::Attribute>::into($value)),*]));
+    ($validation:ident $(($($argument:expr),*))*) => ($crate::validation::Validation::$validation $(($($argument),*))*);
+}
+
 macro_rules! lazy_static {
     (static ref $N:ident : $T:ty = $alloc:expr; $($t:tt)*) => {
-        lazy_static!(PRIV static ref $N : $T = $alloc, |__| {}; $($t)*);
+        lazy_static!(PRIV static ref $N : $T = $alloc, |__| {}, then {}; $($t)*);
     };
     (pub static ref $N:ident : $T:ty = $alloc:expr; $($t:tt)*) => {
-        lazy_static!(PUB static ref $N : $T = $alloc, |__| {}; $($t)*);
+        lazy_static!(PUB static ref $N : $T = $alloc, |__| {}, then {}; $($t)*);
     };
     (pub static ref $N:ident : $T:ty = $alloc:expr, |$S:ident| $init:expr; $($t:tt)*) => {
-        lazy_static!(PUB static ref $N : $T = $alloc, |$S| $init; $($t)*);
+        lazy_static!(PUB static ref $N : $T = $alloc, |$S| $init, then {}; $($t)*);
+    };
+    // `$then` runs once after the first dereference has initialized the value, it may dereference
+    // other statics which refer back to this one.
+    (pub static ref $N:ident : $T:ty = $alloc:expr, |$S:ident| $init:expr, then $then:expr; $($t:tt)*) => {
+        lazy_static!(PUB static ref $N : $T = $alloc, |$S| $init, then $then; $($t)*);
     };
-    ($VIS:ident static ref $N:ident : $T:ty = $alloc:expr,|$S:ident| $init:expr; $($t:tt)*) => {
+    ($VIS:ident static ref $N:ident : $T:ty = $alloc:expr,|$S:ident| $init:expr, then $then:expr; $($t:tt)*) => {
         lazy_static!(MAKE TY $VIS $N);
 
         impl $N {
@@ -103,6 +189,7 @@ macro_rules! lazy_static {
             #[inline(always)]
             unsafe fn initialize(&self) -> *const $T {
                 #[inline(always)]
+                #[allow(unused_variables)]
                 fn __static_ref_initialize($S: &mut $T) { $init; }
 
                 use std::sync::{Once, ONCE_INIT};
@@ -128,6 +215,13 @@ macro_rules! lazy_static {
 
                     let data = self.initialize();
 
+                    use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+
+                    static THEN: AtomicBool = ATOMIC_BOOL_INIT;
+                    if !THEN.swap(true, Ordering::SeqCst) {
+                        $then;
+                    }
+
                     let static_ref = &*data;
                     require_sync(static_ref);
                     static_ref
@@ -0,0 +1,371 @@
+use adapter::Adapter;
+use attribute::{Attribute, AttributeType, Attributes};
+use error::{Error, Result};
+use model::{KeyGeneration, Model};
+use query::{Query, SortOrder};
+use relationship::{self, RelationshipType};
+
+model! {
+    SchemaMigration {
+        type: "schema_migrations",
+        primary_key: "version",
+        key_generation: Manual,
+        attributes: {
+            "version": I64,
+        },
+        relationships: {},
+    }
+}
+
+/// A versioned schema change, along with the SQL to revert it.
+#[derive(Clone, Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub up: String,
+    pub down: String,
+}
+
+impl Migration {
+    pub fn new<S: Into<String>>(version: i64, name: S, up: S, down: S) -> Migration {
+        Migration {
+            version: version,
+            name: name.into(),
+            up: up.into(),
+            down: down.into(),
+        }
+    }
+
+    /// Creates the table of `model`, see `create_table`.
+    pub fn create_table(version: i64, model: Model) -> Migration {
+        Migration::new(version, format!("create_{}", model.ty), create_table(model), drop_table(model))
+    }
+}
+
+/// Applies and reverts migrations, keeping track of the applied versions in the `schema_migrations`
+/// table. Each migration runs in its own transaction.
+pub struct Migrator<'a, A: 'a> {
+    adapter: &'a A,
+    migrations: Vec<Migration>,
+}
+
+impl<'a, A: Adapter> Migrator<'a, A> {
+    pub fn new(adapter: &'a A, mut migrations: Vec<Migration>) -> Migrator<'a, A> {
+        migrations.sort_by(|a, b| a.version.cmp(&b.version));
+        Migrator {
+            adapter: adapter,
+            migrations: migrations,
+        }
+    }
+
+    /// The versions applied so far, in order.
+    pub fn applied(&self) -> Result<Vec<i64>> {
+        try!(self.adapter.execute_batch("CREATE TABLE IF NOT EXISTS schema_migrations (version bigint PRIMARY KEY)"));
+
+        let records = try!(Query::new(&SchemaMigration).order_by("version", SortOrder::ASC).get(self.adapter));
+        let mut versions = vec![];
+        for record in &records {
+            match record.id {
+                Attribute::I64(Some(version)) => versions.push(version),
+                ref id => return Err(Error::TypeMismatch("version".to_string(), AttributeType::I64, id.ty())),
+            }
+        }
+        Ok(versions)
+    }
+
+    pub fn pending(&self) -> Result<Vec<&Migration>> {
+        let applied = try!(self.applied());
+        Ok(self.migrations.iter().filter(|migration| !applied.contains(&migration.version)).collect())
+    }
+
+    /// Applies the pending migrations in order, returns their versions.
+    pub fn migrate(&self) -> Result<Vec<i64>> {
+        for pair in self.migrations.windows(2) {
+            if pair[0].version == pair[1].version {
+                return Err(Error::InvalidParameter(format!("version {}", pair[0].version)));
+            }
+        }
+
+        let mut versions = vec![];
+        for migration in try!(self.pending()) {
+            try!(self.adapter.transaction(|adapter| {
+                try!(adapter.execute_batch(&migration.up));
+                let mut row = Attributes::new();
+                row.insert("version".to_string(), migration.version.into());
+                adapter.insert(&SchemaMigration, &row)
+            }));
+            versions.push(migration.version);
+        }
+        Ok(versions)
+    }
+
+    /// Reverts the last `steps` applied migrations, latest first, returns their versions.
+    pub fn rollback(&self, steps: usize) -> Result<Vec<i64>> {
+        let applied = try!(self.applied());
+
+        let mut versions = vec![];
+        for &version in applied.iter().rev().take(steps) {
+            let migration = try!(self.migrations.iter().find(|migration| migration.version == version)
+                                                       .ok_or(Error::UnknownMigration(version)));
+            try!(self.adapter.transaction(|adapter| {
+                try!(adapter.execute_batch(&migration.down));
+                adapter.delete(&SchemaMigration, &[version.into()])
+            }));
+            versions.push(version);
+        }
+        Ok(versions)
+    }
+}
+
+pub fn sql_type(ty: AttributeType) -> &'static str {
+    match ty {
+        AttributeType::Bool => "boolean",
+        AttributeType::I8 => "\"char\"",
+        AttributeType::I16 => "smallint",
+        AttributeType::I32 => "integer",
+        AttributeType::I64 => "bigint",
+        AttributeType::F32 => "real",
+        AttributeType::F64 => "double precision",
+        AttributeType::String => "text",
+    }
+}
+
+/// Generates the `CREATE TABLE` statement of a model: its primary key (a serial or identity column
+/// if the database generates the ids, see `KeyGeneration`), its attributes, a foreign
+/// key constraint for each `BelongsTo` relationship whose foreign keys are declared as attributes
+/// (records are read by attribute, so other columns couldn't be) and a constraint for each set of
+/// unique attributes. `HasOne` and `HasMany` relationships are stored by the related
+/// tables.
+pub fn create_table(model: Model) -> String {
+    build_create_table(model, false)
+}
+
+/// Like `create_table`, with a `DEFAULT` for each attribute whose default the database can compute,
+/// so that rows inserted without going through `ModelDef::create()` get them too.
+pub fn create_table_with_defaults(model: Model) -> String {
+    build_create_table(model, true)
+}
+
+fn build_create_table(model: Model, defaults: bool) -> String {
+    let mut columns = vec![];
+
+    // The columns of a composite key are attributes, constrained below
+    if let (Some(id_column), Some(ty)) = (model.id_column(), model.id_type()) {
+        let column = match (model.key_generation, ty) {
+            (KeyGeneration::Serial, AttributeType::I16) => "smallserial".to_string(),
+            (KeyGeneration::Serial, AttributeType::I32) => "serial".to_string(),
+            (KeyGeneration::Serial, AttributeType::I64) => "bigserial".to_string(),
+            (KeyGeneration::Identity, ty) => format!("{} GENERATED BY DEFAULT AS IDENTITY", sql_type(ty)),
+            (_, ty) => sql_type(ty).to_string(),
+        };
+        columns.push(format!("{} {} PRIMARY KEY", id_column, column));
+    }
+
+    let mut relationships: Vec<(&str, Model)> = model.relationships.iter().filter_map(|(name, relationship)| {
+        match *relationship {
+            RelationshipType::BelongsTo(target) => Some((&name[..], target)),
+            _ => None,
+        }
+    }).collect();
+    relationships.sort_by(|a, b| a.0.cmp(b.0));
+    // Foreign keys to a single column are declared along with it
+    let references = |name: &str| relationships.iter().find(|&&(relationship, target)| {
+        target.primary_key.len() == 1 && relationship::foreign_key(relationship) == name
+    }).map(|&(_, target)| format!(" REFERENCES {} ({})", target.ty, target.primary_key[0])).unwrap_or_else(String::new);
+
+    let mut attributes: Vec<(&String, &AttributeType)> = model.attributes.iter().filter(|&(name, _)| model.id_column() != Some(name)).collect();
+    attributes.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, &ty) in attributes {
+        let default = match model.defaults.get(name).and_then(|default| default.to_sql()) {
+            Some(ref sql) if defaults => format!(" DEFAULT {}", sql),
+            _ => String::new(),
+        };
+        columns.push(format!("{} {}{}{}", name, sql_type(ty), default, references(name)));
+    }
+
+    if model.primary_key.len() > 1 {
+        columns.push(format!("PRIMARY KEY ({})", model.primary_key.join(", ")));
+    }
+
+    for &(name, target) in &relationships {
+        let declared = relationship::foreign_keys(name, target).iter().all(|name| model.attributes.contains_key(name));
+        if target.primary_key.len() > 1 && declared {
+            columns.push(format!("FOREIGN KEY ({}) REFERENCES {} ({})",
+                                 relationship::foreign_keys(name, target).join(", "), target.ty, target.primary_key.join(", ")));
+        }
+    }
+
+    for unique in &model.unique {
+        columns.push(format!("UNIQUE ({})", unique.join(", ")));
+    }
+
+    format!("CREATE TABLE {} (\n    {}\n)", model.ty, columns.join(",\n    "))
+}
+
+pub fn drop_table(model: Model) -> String {
+    format!("DROP TABLE {}", model.ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adapter::memory_adapter::MemoryAdapter;
+    use error::Error;
+
+    model! {
+        Author {
+            type: "author",
+            attributes: {
+                "name": String,
+            },
+            relationships: {
+                "books": HasMany<Book>,
+            },
+            unique: [["name"],],
+        },
+
+        Book {
+            type: "book",
+            attributes: {
+                "title": String,
+                "pages": I32,
+                "author_id": I32,
+            },
+            relationships: {
+                "author": BelongsTo<Author>,
+            },
+        },
+
+        Comment {
+            type: "comment",
+            attributes: {
+                "body": String,
+            },
+            relationships: {
+                "book": BelongsTo<Book>,
+            },
+        }
+    }
+
+    model! {
+        Event {
+            type: "event",
+            primary_key: "id",
+            attributes: {
+                "id": I64,
+            },
+            relationships: {},
+        },
+
+        Token {
+            type: "token",
+            primary_key: "id",
+            key_generation: UuidV7,
+            attributes: {
+                "secret": String,
+                "issued_at": String,
+                "uses": I64,
+                "label": String,
+            },
+            relationships: {},
+            defaults: {
+                "secret": uuid(),
+                "issued_at": now(),
+                "uses": 0,
+                "label": "it's new",
+            },
+        },
+
+        Counter {
+            type: "counter",
+            primary_key: "id",
+            key_generation: Identity,
+            attributes: {},
+            relationships: {},
+        },
+
+        Edition {
+            type: "edition",
+            primary_key: ["isbn", "printing"],
+            attributes: {
+                "isbn": String,
+                "printing": I16,
+            },
+            relationships: {},
+        },
+
+        Review {
+            type: "review",
+            primary_key: "id",
+            attributes: {
+                "edition_isbn": String,
+                "edition_printing": I16,
+            },
+            relationships: {
+                "edition": BelongsTo<Edition>,
+            },
+        }
+    }
+
+    #[test]
+    fn test_create_table() {
+        assert_eq!(create_table(&Author), "CREATE TABLE author (\n    \
+                                               id serial PRIMARY KEY,\n    \
+                                               name text,\n    \
+                                               UNIQUE (name)\n)");
+        assert_eq!(create_table(&Book), "CREATE TABLE book (\n    \
+                                             id serial PRIMARY KEY,\n    \
+                                             author_id integer REFERENCES author (id),\n    \
+                                             pages integer,\n    \
+                                             title text\n)");
+        // Undeclared foreign keys couldn't be read
+        assert_eq!(create_table(&Comment), "CREATE TABLE comment (\n    id serial PRIMARY KEY,\n    body text\n)");
+        assert_eq!(create_table(&Event), "CREATE TABLE event (\n    id bigserial PRIMARY KEY\n)");
+        assert_eq!(create_table(&Token), "CREATE TABLE token (\n    \
+                                              id text PRIMARY KEY,\n    \
+                                              issued_at text,\n    \
+                                              label text,\n    \
+                                              secret text,\n    \
+                                              uses bigint\n)");
+        assert_eq!(create_table_with_defaults(&Token), "CREATE TABLE token (\n    \
+                                                            id text PRIMARY KEY,\n    \
+                                                            issued_at text DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"'),\n    \
+                                                            label text DEFAULT 'it''s new',\n    \
+                                                            secret text DEFAULT gen_random_uuid()::text,\n    \
+                                                            uses bigint DEFAULT 0\n)");
+        assert_eq!(create_table(&Counter), "CREATE TABLE counter (\n    id integer GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY\n)");
+        assert_eq!(create_table(&Edition), "CREATE TABLE edition (\n    \
+                                                isbn text,\n    \
+                                                printing smallint,\n    \
+                                                PRIMARY KEY (isbn, printing)\n)");
+        assert_eq!(create_table(&Review), "CREATE TABLE review (\n    \
+                                               id serial PRIMARY KEY,\n    \
+                                               edition_isbn text,\n    \
+                                               edition_printing smallint,\n    \
+                                               FOREIGN KEY (edition_isbn, edition_printing) REFERENCES edition (isbn, printing)\n)");
+    }
+
+    #[test]
+    fn test_migrate_and_rollback() {
+        let adapter = MemoryAdapter::new();
+        let migrations = vec![
+            Migration::create_table(2, &Book),
+            Migration::create_table(1, &Author),
+        ];
+        let migrator = Migrator::new(&adapter, migrations.clone());
+        assert_eq!(migrator.migrate().unwrap(), vec![1, 2]);
+        assert_eq!(migrator.migrate().unwrap(), vec![]);
+        assert_eq!(migrator.applied().unwrap(), vec![1, 2]);
+        assert!(adapter.executed.borrow().iter().any(|sql| sql.starts_with("CREATE TABLE book")));
+
+        assert_eq!(migrator.rollback(1).unwrap(), vec![2]);
+        assert_eq!(adapter.executed.borrow().last().unwrap(), "DROP TABLE book");
+        assert_eq!(migrator.pending().unwrap().iter().map(|migration| migration.version).collect::<Vec<_>>(), vec![2]);
+
+        let forgetful = Migrator::new(&adapter, migrations[..1].to_vec());
+        match forgetful.rollback(1) {
+            Err(Error::UnknownMigration(1)) => {},
+            _ => panic!(),
+        }
+    }
+}
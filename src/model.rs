@@ -1,40 +1,164 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::slice;
+use std::vec;
 
-use attribute::{Attribute, AttributeType, Attributes, AttributeTypes};
-use relationship::{Relationship, RelationshipType, Relationships, RelationshipTypes};
-use query::Query;
+use adapter::Adapter;
+use attribute::{Attribute, AttributeType, Attributes, AttributeTypes, FromAttribute};
+use default::Defaults;
+use error::{Error, Result};
+use pagination::Cursor;
+use relationship::{self, Relationship, RelationshipType, Relationships, RelationshipTypes};
+use query::{Filter, Query, SortOrder};
+use serializer::Serializer;
+use upsert::Upsert;
+use uuid;
+use validation::{Errors, Validations};
 
 pub type Model = &'static ModelDef;
 
+/// The values of the primary key columns of a record, in order.
+pub type Key = Vec<Attribute>;
+
+/// Identifies a record by `(ty, key)`.
+pub type Identity = (&'static str, Key);
+
+/// Declared with `model!`, or built at runtime (see `schema`). Either way a model lives for the
+/// rest of the program, so records and queries can borrow its strings.
 #[derive(Debug)]
 pub struct ModelDef {
-    pub ty: &'static str,
-    pub primary_key: &'static str,
-    pub attributes: HashMap<&'static str, AttributeType>,
-    pub relationships: HashMap<&'static str, RelationshipType>,
+    pub ty: String,
+    /// The primary key columns, usually a single one. The columns of a composite key have to be
+    /// declared as attributes.
+    pub primary_key: Vec<String>,
+    /// How the ids of new records are generated, `Serial` unless declared otherwise.
+    pub key_generation: KeyGeneration,
+    pub attributes: AttributeTypes,
+    pub relationships: RelationshipTypes,
+    /// Set on the records made by `create()`.
+    pub defaults: Defaults,
+    /// Checked by `Record::validate()` before records are written.
+    pub validations: Validations,
+    /// Sets of attributes declared unique, which can be used as conflict targets of an upsert.
+    pub unique: Vec<Vec<String>>,
+}
+
+/// The primary key given to `model!`, either a column name or an array of column names.
+pub trait KeyColumns {
+    fn key_columns(&self) -> Vec<String>;
+}
+
+impl KeyColumns for str {
+    fn key_columns(&self) -> Vec<String> {
+        vec![self.to_string()]
+    }
+}
+
+impl<'a> KeyColumns for [&'a str] {
+    fn key_columns(&self) -> Vec<String> {
+        self.iter().map(|name| name.to_string()).collect()
+    }
 }
 
-pub fn model(ty: &'static str, primary_key: &'static str) -> ModelDef {
+/// How the ids of new records are generated, for models keyed by a single column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyGeneration {
+    /// Ids are set by the application.
+    Manual,
+    /// The database assigns ids from a sequence, with a `serial` column.
+    Serial,
+    /// The database assigns ids from an identity column.
+    Identity,
+    /// Random UUIDs are assigned before insert, see `uuid::v4`.
+    UuidV4,
+    /// Time ordered UUIDs are assigned before insert, see `uuid::v7`.
+    UuidV7,
+    /// Ids returned by the given function are assigned before insert.
+    Custom(fn() -> Attribute),
+}
+
+impl KeyGeneration {
+    pub fn by_database(&self) -> bool {
+        match *self {
+            KeyGeneration::Serial | KeyGeneration::Identity => true,
+            _ => false,
+        }
+    }
+}
+
+pub fn model<S: Into<String>>(ty: S, primary_key: Vec<String>) -> ModelDef {
     ModelDef {
-        ty: ty,
+        ty: ty.into(),
         primary_key: primary_key,
+        key_generation: KeyGeneration::Serial,
         attributes: AttributeTypes::new(),
         relationships: RelationshipTypes::new(),
+        defaults: Defaults::new(),
+        validations: Validations::new(),
+        unique: vec![],
     }
 }
 
 impl ModelDef {
+    /// A new record, with the declared defaults set as changed attributes so that they are
+    /// written on insert.
     pub fn create(&'static self) -> Record {
+        let mut record = self.create_empty();
+        for (name, default) in &self.defaults {
+            if let Some(&ty) = self.attributes.get(name) {
+                record.set(name, default.value(ty));
+                record.defaulted.push(name.clone());
+            }
+        }
+        record
+    }
+
+    /// Checks that the defaults are declared for attributes of the model, with values that can be
+    /// widened to their types. `model!` does so when the model is initialized.
+    pub fn check_defaults(&self) -> Result<()> {
+        for (name, default) in &self.defaults {
+            let &ty = try!(self.attributes.get(name).ok_or_else(|| Error::UnknownAttribute(name.clone())));
+            if let Some(found) = default.ty() {
+                if found.to_attribute().coerce(ty).is_none() {
+                    return Err(Error::TypeMismatch(name.clone(), ty, found));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `create`, without the defaults, e.g. to read a stored row into.
+    pub fn create_empty(&'static self) -> Record {
         Record {
-            id: Attribute::String(None),
-            ty: self.ty,
+            id: self.id_type().map_or(Attribute::String(None), |ty| ty.to_attribute()),
+            ty: &self.ty,
             attributes: self.attributes.iter().map(|(name, ty)| (name.to_string(), ty.to_attribute())).collect(),
             relationships: self.relationships.iter().map(|(name, ty)| (name.to_string(), ty.to_relationship())).collect(),
+            primary_key: &self.primary_key,
+            validations: &self.validations,
+            original: Attributes::new(),
+            defaulted: vec![],
         }
     }
 
-    pub fn find<'a>(&'static self, id: &'a Attribute) -> Query<'a> {
-        Query::new(self).where_(self.primary_key).eq(id).limit(1)
+    /// Finds a record by the id of a model keyed by a single column, see `find_by_key` for
+    /// composite keys.
+    pub fn find<'a>(&'static self, id: &'a Attribute) -> Result<Query<'a>> {
+        self.find_by_key(vec![id])
+    }
+
+    /// Finds a record by primary key, given one attribute per key column in order. Fails with
+    /// `InvalidParameter` if the number of attributes doesn't match.
+    pub fn find_by_key<'a>(&'static self, key: Vec<&'a Attribute>) -> Result<Query<'a>> {
+        if key.len() != self.primary_key.len() {
+            return Err(Error::InvalidParameter("key".to_string()));
+        }
+
+        let mut query = Query::new(self);
+        for (i, (name, attribute)) in self.primary_key.iter().zip(key).enumerate() {
+            query = if i == 0 { query.where_(name).eq(attribute) } else { query.and(name).eq(attribute) };
+        }
+        Ok(query.limit(1))
     }
 
     pub fn find_by<'a>(&'static self, name: &'a str, filter: &'a Attribute) -> Query<'a> {
@@ -44,31 +168,338 @@ impl ModelDef {
     pub fn find_in<'a>(&'static self, name: &'a str, filters: Vec<&'a Attribute>) -> Query<'a> {
         Query::new(self).where_(name).in_(filters)
     }
+
+    /// The column held by `Record.id`, which is the primary key unless it is composite.
+    pub fn id_column(&self) -> Option<&str> {
+        if self.primary_key.len() == 1 {
+            Some(&self.primary_key[0])
+        } else {
+            None
+        }
+    }
+
+    /// The type of `Record.id`: that of the key attribute if declared, otherwise a string for UUIDs
+    /// and an `I32` for the other generation strategies.
+    pub fn id_type(&self) -> Option<AttributeType> {
+        self.id_column().map(|name| match self.attributes.get(name) {
+            Some(&ty) => ty,
+            None => match self.key_generation {
+                KeyGeneration::UuidV4 | KeyGeneration::UuidV7 => AttributeType::String,
+                _ => AttributeType::I32,
+            },
+        })
+    }
+
+    /// Generates the id of a new record, unless the key is composite, set by the application or
+    /// generated by the database.
+    pub fn generate_id(&self) -> Option<Attribute> {
+        if self.id_column().is_none() {
+            return None;
+        }
+        match self.key_generation {
+            KeyGeneration::UuidV4 => Some(uuid::v4().into()),
+            KeyGeneration::UuidV7 => Some(uuid::v7().into()),
+            KeyGeneration::Custom(generate) => Some(generate()),
+            _ => None,
+        }
+    }
+
+    /// The row inserting `record`, with a generated id if it has none, see `generate_id`.
+    pub fn insert_row(&self, record: &Record) -> Attributes {
+        let mut row = record.to_row();
+        if let (Some(id_column), true) = (self.id_column(), record.id.is_null()) {
+            if let Some(id) = self.generate_id() {
+                row.insert(id_column.to_string(), id);
+            }
+        }
+        row
+    }
+
+    pub fn is_primary_key(&self, name: &str) -> bool {
+        self.primary_key.iter().any(|column| column == name)
+    }
+
+    /// The attributes of `target` referring to this model in a `HasOne` or `HasMany` relationship:
+    /// the foreign keys of `target`'s `BelongsTo` relationship back to this model if it has one, or
+    /// those of a relationship named after the type otherwise, see `relationship::foreign_keys`.
+    pub fn foreign_key_in(&'static self, target: Model) -> Vec<String> {
+        for (name, relationship) in &target.relationships {
+            if let RelationshipType::BelongsTo(parent) = *relationship {
+                if parent as *const ModelDef == self as *const ModelDef {
+                    return relationship::foreign_keys(name, self);
+                }
+            }
+        }
+        relationship::foreign_keys(self.ty.rsplit('.').next().unwrap(), self)
+    }
+
+    /// The rows inserting `records`, see `insert_row`. Fails if a record is of another model or
+    /// invalid.
+    pub fn insert_rows(&self, records: &[Record]) -> Result<Vec<Attributes>> {
+        let mut rows = vec![];
+        for record in records {
+            if record.ty != self.ty {
+                return Err(Error::InvalidParameter(record.ty.to_string()));
+            }
+            try!(record.validate());
+            rows.push(self.insert_row(record));
+        }
+        Ok(rows)
+    }
+
+    /// Inserts the records in as few statements as possible and returns their keys in order,
+    /// generated for the records without one. Nothing is inserted if a record is invalid.
+    pub fn insert_all<A: Adapter>(&'static self, adapter: &A, records: Vec<Record>) -> Result<Vec<Key>> {
+        let rows = try!(self.insert_rows(&records));
+        let stored = try!(adapter.insert_all(self, &rows));
+        stored.into_iter().map(|mut row| {
+            self.primary_key.iter().map(|name| row.remove(name).ok_or_else(|| Error::UnknownAttribute(name.clone()))).collect()
+        }).collect()
+    }
+
+    /// Inserts the records, updating the stored ones they conflict with instead.
+    pub fn upsert<'a>(&'static self, records: Vec<&'a Record>) -> Upsert<'a> {
+        Upsert::new(self, records)
+    }
+
+    /// Whether `names` is the primary key or one of the unique attribute sets, in any order.
+    pub fn is_key(&self, names: &[&str]) -> bool {
+        let same = |key: &[String]| key.len() == names.len() && key.iter().all(|name| names.contains(&name.as_str()));
+        same(&self.primary_key) || self.unique.iter().any(|key| same(key))
+    }
+
+    /// Checks that a dotted path like `"orders.price"` follows relationships of this model and
+    /// ends with an attribute (or the primary key) of the last related model. Paths through
+    /// `HasMany` relationships are refused unless `to_many` is set.
+    pub fn check_path(&'static self, path: &str, to_many: bool) -> Result<()> {
+        let segments: Vec<&str> = path.split('.').collect();
+        let (attribute, relationships) = segments.split_last().unwrap();
+
+        let mut model = self;
+        for name in relationships {
+            model = match model.relationships.get(*name) {
+                Some(&RelationshipType::HasMany(_)) if !to_many => return Err(Error::InvalidPath(path.to_string())),
+                Some(&RelationshipType::BelongsTo(target)) |
+                Some(&RelationshipType::HasOne(target)) |
+                Some(&RelationshipType::HasMany(target)) => target,
+                None => return Err(Error::UnknownRelationship(name.to_string())),
+            };
+        }
+
+        if model.is_primary_key(attribute) || model.attributes.contains_key(*attribute) {
+            Ok(())
+        } else {
+            Err(Error::UnknownAttribute(path.to_string()))
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct Record<'a> {
+pub struct Record {
+    /// The primary key of a model keyed by a single column. The columns of a composite key are
+    /// attributes instead, and this stays null, see `key`.
     pub id: Attribute,
     pub ty: &'static str,
     pub attributes: Attributes,
-    pub relationships: Relationships<'a>,
+    pub relationships: Relationships,
+    primary_key: &'static [String],
+    validations: &'static Validations,
+    // Original values of the attributes changed by `set` since the record was last marked clean.
+    original: Attributes,
+    // The attributes set to their defaults by `create` and not set since.
+    defaulted: Vec<String>,
 }
 
-impl<'a> Record<'a> {
+impl Record {
+    /// The values of the primary key columns.
+    pub fn key(&self) -> Key {
+        self.primary_key.iter().map(|name| {
+            self.value(name).cloned().unwrap_or(Attribute::String(None))
+        }).collect()
+    }
+
+    /// Whether no column of the primary key is null, i.e. the record can be identified.
+    pub fn has_key(&self) -> bool {
+        self.primary_key.iter().all(|name| self.value(name).map_or(false, |value| !value.is_null()))
+    }
+
+    pub fn identity(&self) -> Identity {
+        (self.ty, self.key())
+    }
+
+    /// The attributes along with the primary key, unless it is null.
+    pub fn to_row(&self) -> Attributes {
+        let mut row = self.attributes.clone();
+        if self.primary_key.len() == 1 && !self.id.is_null() {
+            row.insert(self.primary_key[0].clone(), self.id.clone());
+        }
+        row
+    }
+
     pub fn get(&self, name: &str) -> Option<&Attribute> {
         self.attributes.get(name)
     }
 
+    /// Like `get`, but returns the id if `name` is the primary key.
+    pub fn value(&self, name: &str) -> Option<&Attribute> {
+        if self.primary_key.len() == 1 && name == self.primary_key[0] {
+            Some(&self.id)
+        } else {
+            self.attributes.get(name)
+        }
+    }
+
+    /// Reads an attribute as `T`. Use `Option<T>` to read a nullable attribute without getting an
+    /// error for null values.
+    pub fn get_as<T: FromAttribute>(&self, name: &str) -> Result<T> {
+        let attribute = try!(self.attributes.get(name).ok_or_else(|| Error::UnknownAttribute(name.to_string())));
+        match T::from_attribute(attribute) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(Error::NullAttribute(name.to_string())),
+            Err(expected) => Err(Error::TypeMismatch(name.to_string(), expected, attribute.ty())),
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<bool> {
+        self.get_as(name)
+    }
+
+    pub fn get_i8(&self, name: &str) -> Result<i8> {
+        self.get_as(name)
+    }
+
+    pub fn get_i16(&self, name: &str) -> Result<i16> {
+        self.get_as(name)
+    }
+
+    pub fn get_i32(&self, name: &str) -> Result<i32> {
+        self.get_as(name)
+    }
+
+    pub fn get_i64(&self, name: &str) -> Result<i64> {
+        self.get_as(name)
+    }
+
+    pub fn get_f32(&self, name: &str) -> Result<f32> {
+        self.get_as(name)
+    }
+
+    pub fn get_f64(&self, name: &str) -> Result<f64> {
+        self.get_as(name)
+    }
+
+    pub fn get_string(&self, name: &str) -> Result<String> {
+        self.get_as(name)
+    }
+
     pub fn set(&mut self, name: &str, attribute: Attribute) {
+        self.defaulted.retain(|defaulted| defaulted != name);
         if let Some(a) = self.attributes.get_mut(name) {
+            if !self.original.contains_key(name) {
+                if *a != attribute {
+                    self.original.insert(name.to_string(), a.clone());
+                }
+            } else if self.original.get(name) == Some(&attribute) {
+                self.original.remove(name);
+            }
             *a = attribute;
         }
     }
 
-    pub fn get_one(&self, name: &str) -> Option<&Option<Record>> {
+    /// Like `set`, but fails for unknown attributes and for values whose type doesn't match the
+    /// declared one.
+    pub fn try_set(&mut self, name: &str, attribute: Attribute) -> Result<()> {
+        let expected = try!(self.attribute_type(name));
+        if attribute.ty() != expected {
+            return Err(Error::TypeMismatch(name.to_string(), expected, attribute.ty()));
+        }
+        self.set(name, attribute);
+        Ok(())
+    }
+
+    /// Like `try_set`, but widens the value to the declared type where that is lossless, e.g. `I32`
+    /// to `I64`.
+    pub fn try_set_coerced(&mut self, name: &str, attribute: Attribute) -> Result<()> {
+        let expected = try!(self.attribute_type(name));
+        let found = attribute.ty();
+        match attribute.coerce(expected) {
+            Some(attribute) => {
+                self.set(name, attribute);
+                Ok(())
+            },
+            None => Err(Error::TypeMismatch(name.to_string(), expected, found)),
+        }
+    }
+
+    fn attribute_type(&self, name: &str) -> Result<AttributeType> {
+        self.attributes.get(name).map(|a| a.ty()).ok_or_else(|| Error::UnknownAttribute(name.to_string()))
+    }
+
+    /// Checks the validations declared for the model, and fails with all the errors found if
+    /// there are any.
+    pub fn validate(&self) -> Result<()> {
+        let null = Attribute::String(None);
+        let mut errors = Errors::new();
+        for (name, validations) in self.validations {
+            let attribute = self.value(name).unwrap_or(&null);
+            for validation in validations {
+                if let Err(message) = validation.check(attribute) {
+                    errors.add(name, message);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidRecord(errors))
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.original.len() > 0
+    }
+
+    pub fn is_changed(&self, name: &str) -> bool {
+        self.original.contains_key(name)
+    }
+
+    pub fn changed_attributes(&self) -> Vec<&str> {
+        self.original.keys().map(|name| name.as_str()).collect()
+    }
+
+    /// Whether the attribute holds the default set by `ModelDef::create`, i.e. it hasn't been set
+    /// since.
+    pub fn is_defaulted(&self, name: &str) -> bool {
+        self.defaulted.iter().any(|defaulted| defaulted == name)
+    }
+
+    /// Returns `(old, new)` values of every changed attribute.
+    pub fn changes(&self) -> HashMap<&str, (&Attribute, &Attribute)> {
+        self.original.iter().map(|(name, old)| (name.as_str(), (old, &self.attributes[name]))).collect()
+    }
+
+    /// Restores the original values of all changed attributes.
+    pub fn reset(&mut self) {
+        for (name, attribute) in self.original.drain() {
+            self.attributes.insert(name, attribute);
+        }
+        self.defaulted.clear();
+    }
+
+    /// Accepts the current values as the original ones, e.g. after they have been loaded or saved.
+    pub fn mark_clean(&mut self) {
+        self.original.clear();
+        self.defaulted.clear();
+    }
+
+    /// Returns the identity of the related record of a `BelongsTo` or `HasOne` relationship, which
+    /// can be resolved through the `RecordSet` it was loaded into.
+    pub fn get_one(&self, name: &str) -> Option<&Identity> {
         if let Some(relationship) = self.relationships.get(name) {
             match *relationship {
-                Relationship::HasOne(ref r) => Some(r),
+                Relationship::BelongsTo(Some(ref r)) => Some(r),
+                Relationship::HasOne(Some(ref r)) => Some(r),
                 _ => None,
             }
         } else {
@@ -76,7 +507,7 @@ impl<'a> Record<'a> {
         }
     }
 
-    pub fn get_many(&self, name: &str) -> Option<&Vec<Record>> {
+    pub fn get_many(&self, name: &str) -> Option<&Vec<Identity>> {
         if let Some(relationship) = self.relationships.get(name) {
             match *relationship {
                 Relationship::HasMany(ref r) => Some(r),
@@ -88,25 +519,326 @@ impl<'a> Record<'a> {
     }
 }
 
+/// The records returned by a query, along with the related records loaded for them.
+///
+/// The set owns every record of the graph, relationships only refer to records by identity. This
+/// way cycles like user -> orders -> customer need no special care.
 #[derive(Debug)]
-pub struct RecordSet<'a> {
-    records: Vec<Record<'a>>
+pub struct RecordSet {
+    // The query results come first, followed by the related records.
+    records: Vec<Record>,
+    len: usize,
+    index: HashMap<Identity, usize>,
+    /// Set when paginating by cursor and there are records before the first one.
+    pub prev_cursor: Option<Cursor>,
+    /// Set when paginating by cursor and there are records after the last one.
+    pub next_cursor: Option<Cursor>,
 }
 
-impl<'a> RecordSet<'a> {
-    pub fn new(records: Vec<Record<'a>>) -> RecordSet<'a> {
-        RecordSet {
-            records: records,
+impl RecordSet {
+    pub fn new(records: Vec<Record>) -> RecordSet {
+        let mut record_set = RecordSet {
+            records: vec![],
+            len: 0,
+            index: HashMap::new(),
+            prev_cursor: None,
+            next_cursor: None,
+        };
+        for record in records {
+            record_set.push(record);
         }
+        record_set.len = record_set.records.len();
+        record_set
+    }
+
+    pub fn iter(&self) -> slice::Iter<Record> {
+        self.records[..self.len].iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn first(&self) -> Option<&Record> {
-        self.records.iter().next()
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<&Record> {
+        self.iter().last()
+    }
+
+    /// Finds a query result by its id.
+    pub fn get_by_id(&self, id: &Attribute) -> Option<&Record> {
+        self.iter().find(|record| record.id == *id)
+    }
+
+    /// Returns the value of `name` of every query result which has that attribute.
+    pub fn pluck(&self, name: &str) -> Vec<&Attribute> {
+        self.iter().filter_map(|record| record.value(name)).collect()
+    }
+
+    /// Sorts the query results in memory, nulls sort last in ascending order as in Postgres.
+    pub fn sort_by(&mut self, name: &str, order: SortOrder) {
+        self.records[..self.len].sort_by(|a, b| {
+            let ordering = match (a.value(name), b.value(name)) {
+                (Some(a), Some(b)) => a.compare(b).unwrap_or(Ordering::Equal),
+                _ => Ordering::Equal,
+            };
+            match order {
+                SortOrder::ASC => ordering,
+                SortOrder::DESC => ordering.reverse(),
+            }
+        });
+        self.reindex();
+    }
+
+    pub fn reverse(&mut self) {
+        self.records[..self.len].reverse();
+        self.reindex();
+    }
+
+    /// Keeps the first `len` query results, along with the related records.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.records.drain(len..self.len);
+            self.len = len;
+            self.reindex();
+        }
+    }
+
+    /// Returns the query results matching `filter`, see `Filter::matches`. Fails if the filter has
+    /// subqueries.
+    pub fn filter(&self, filter: &Filter) -> Result<Vec<&Record>> {
+        try!(filter.check_local());
+        Ok(self.iter().filter(|record| filter.matches(record)).collect())
+    }
+
+    /// Groups the query results by the value of `name`.
+    pub fn group_by(&self, name: &str) -> HashMap<Attribute, Vec<&Record>> {
+        let mut groups = HashMap::new();
+        for record in self.iter() {
+            if let Some(key) = record.value(name) {
+                groups.entry(key.clone()).or_insert_with(Vec::new).push(record);
+            }
+        }
+        groups
+    }
+
+    /// Indexes the query results by the value of `name`, later records win on duplicated values.
+    pub fn index_by(&self, name: &str) -> HashMap<Attribute, &Record> {
+        self.iter().filter_map(|record| record.value(name).map(|key| (key.clone(), record))).collect()
+    }
+
+    /// Returns the query results, dropping the related records.
+    pub fn into_records(self) -> Vec<Record> {
+        let mut records = self.records;
+        records.truncate(self.len);
+        records
+    }
+
+    /// Looks up a record of the graph, either a query result or a related record.
+    pub fn get(&self, identity: &Identity) -> Option<&Record> {
+        self.index.get(identity).map(|&i| &self.records[i])
+    }
+
+    pub fn get_one(&self, record: &Record, name: &str) -> Option<&Record> {
+        record.get_one(name).and_then(|identity| self.get(identity))
+    }
+
+    pub fn get_many(&self, record: &Record, name: &str) -> Vec<&Record> {
+        match record.get_many(name) {
+            Some(identities) => identities.iter().filter_map(|identity| self.get(identity)).collect(),
+            None => vec![],
+        }
+    }
+
+    /// Loads the records along the relationship path `path` (e.g. `"orders.customer"`), starting
+    /// from the query results which are of `model`.
+    pub fn load<A: Adapter, S: Serializer>(&mut self, adapter: &A, serializer: &S, model: Model, path: &str) -> Result<()> {
+        let mut model = model;
+        let mut level: Vec<usize> = (0..self.len).collect();
+
+        for name in path.split('.') {
+            let relationship = try!(model.relationships.get(name).ok_or_else(|| Error::UnknownRelationship(name.to_string())));
+            level = match *relationship {
+                RelationshipType::BelongsTo(target) => {
+                    let loaded = try!(self.load_belongs_to(adapter, serializer, &level, name, target));
+                    model = target;
+                    loaded
+                },
+                RelationshipType::HasOne(target) | RelationshipType::HasMany(target) => {
+                    let loaded = try!(self.load_has_many(adapter, serializer, &level, name, model, target));
+                    model = target;
+                    loaded
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+    fn load_belongs_to<A: Adapter, S: Serializer>(&mut self, adapter: &A, serializer: &S, level: &[usize], name: &str, target: Model) -> Result<Vec<usize>> {
+        let foreign_keys = relationship::foreign_keys(name, target);
+        let mut keys = vec![];
+        for &i in level {
+            if let Some(key) = values_of(&self.records[i], &foreign_keys) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        let loaded = try!(self.fetch(adapter, serializer, target, &target.primary_key, &keys));
+
+        for &i in level {
+            let parent = match values_of(&self.records[i], &foreign_keys) {
+                Some(key) => {
+                    let identity = (&target.ty[..], key);
+                    if self.index.contains_key(&identity) { Some(identity) } else { None }
+                },
+                None => None,
+            };
+            self.records[i].relationships.insert(name.to_string(), Relationship::BelongsTo(parent));
+        }
+
+        Ok(loaded)
+    }
+
+    fn load_has_many<A: Adapter, S: Serializer>(&mut self, adapter: &A, serializer: &S, level: &[usize], name: &str, model: Model, target: Model) -> Result<Vec<usize>> {
+        let foreign_keys = model.foreign_key_in(target);
+        let mut keys = vec![];
+        for &i in level {
+            let record = &self.records[i];
+            if record.has_key() {
+                let key = record.key();
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        let loaded = try!(self.fetch(adapter, serializer, target, &foreign_keys, &keys));
+
+        let mut children: HashMap<Key, Vec<Identity>> = HashMap::new();
+        for &j in &loaded {
+            if let Some(key) = values_of(&self.records[j], &foreign_keys) {
+                children.entry(key).or_insert_with(Vec::new).push(self.records[j].identity());
+            }
+        }
+
+        for &i in level {
+            let related = children.get(&self.records[i].key()).cloned().unwrap_or_else(Vec::new);
+            let relationship = match model.relationships[name] {
+                RelationshipType::HasOne(_) => Relationship::HasOne(related.into_iter().next()),
+                _ => Relationship::HasMany(related),
+            };
+            self.records[i].relationships.insert(name.to_string(), relationship);
+        }
+
+        Ok(loaded)
+    }
+
+    // Queries `model` for the records whose `names` are one of `keys` and adds them to the set.
+    fn fetch<A: Adapter, S: Serializer>(&mut self, adapter: &A, serializer: &S, model: Model, names: &[String], keys: &[Key]) -> Result<Vec<usize>> {
+        if keys.len() == 0 {
+            return Ok(vec![]);
+        }
+
+        let query = if names.len() == 1 {
+            model.find_in(&names[0], keys.iter().map(|key| &key[0]).collect())
+        } else {
+            // `(a = $1 AND b = $2) OR (a = $3 AND b = $4) OR ..`
+            let mut query = Query::new(model);
+            query.filter = keys.iter().map(|key| {
+                names.iter().zip(key).map(|(name, attribute)| Filter::Equal(name, attribute))
+                     .fold(None, |filter, equal| Some(match filter {
+                         Some(filter) => Filter::And(Box::new(filter), Box::new(equal)),
+                         None => equal,
+                     })).unwrap()
+            }).fold(None, |filter, term| Some(match filter {
+                Some(filter) => Filter::Or(Box::new(filter), Box::new(term)),
+                None => term,
+            }));
+            query
+        };
+
+        let record_set = try!(query.get_with(adapter, serializer));
+        Ok(record_set.into_records().into_iter().map(|record| self.push(record)).collect())
+    }
+
+    fn reindex(&mut self) {
+        let mut index = HashMap::new();
+        for (i, record) in self.records.iter().enumerate() {
+            if record.has_key() {
+                index.insert(record.identity(), i);
+            }
+        }
+        self.index = index;
+    }
+
+    // Adds a record unless a record with the same identity is already there, and returns its
+    // position.
+    fn push(&mut self, record: Record) -> usize {
+        if record.has_key() {
+            let identity = record.identity();
+            if let Some(&i) = self.index.get(&identity) {
+                return i;
+            }
+            self.index.insert(identity, self.records.len());
+        }
+        self.records.push(record);
+        self.records.len() - 1
+    }
+}
+
+// The values of `names` in `record`, unless one of them is null or missing.
+fn values_of(record: &Record, names: &[String]) -> Option<Key> {
+    let mut key = vec![];
+    for name in names {
+        match record.value(name) {
+            Some(value) if !value.is_null() => key.push(value.clone()),
+            _ => return None,
+        }
+    }
+    Some(key)
+}
+
+impl IntoIterator for RecordSet {
+    type Item = Record;
+    type IntoIter = vec::IntoIter<Record>;
+
+    fn into_iter(self) -> vec::IntoIter<Record> {
+        self.into_records().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a RecordSet {
+    type Item = &'a Record;
+    type IntoIter = slice::Iter<'a, Record>;
+
+    fn into_iter(self) -> slice::Iter<'a, Record> {
+        self.iter()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryFrom;
+
+    use adapter::Adapter;
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::{Attribute, AttributeType, Attributes};
+    use error::Error;
+    use query::{Query, SortOrder};
+    use default::DefaultValue;
+    use pagination::Cursor;
+    use super::RecordSet;
+    use std::sync::atomic::{self, AtomicUsize, ATOMIC_USIZE_INIT};
+
     model! {
         User {
             type: "user",
@@ -126,6 +858,7 @@ mod tests {
 
             attributes: {
                 "price": F32,
+                "customer_id": I32,
             },
 
             relationships: {
@@ -134,9 +867,361 @@ mod tests {
         }
     }
 
+    model! {
+        Sensor {
+            type: "sensor",
+            primary_key: "id",
+            attributes: {},
+            relationships: {
+                "readings": HasMany<Reading>,
+            },
+        },
+
+        Reading {
+            type: "reading",
+            primary_key: ["sensor_id", "taken_at"],
+            attributes: {
+                "sensor_id": I32,
+                "taken_at": I64,
+                "value": F64,
+            },
+            relationships: {
+                "sensor": BelongsTo<Sensor>,
+                "notes": HasMany<Note>,
+            },
+        },
+
+        Note {
+            type: "note",
+            primary_key: "id",
+            attributes: {
+                "reading_sensor_id": I32,
+                "reading_taken_at": I64,
+            },
+            relationships: {
+                "reading": BelongsTo<Reading>,
+            },
+        }
+    }
+
+    model! {
+        Event {
+            type: "event",
+            primary_key: "id",
+            attributes: {
+                "id": I64,
+                "title": String,
+                "seats": I64,
+                "rank": I32,
+                "reference": String,
+            },
+            relationships: {},
+            defaults: {
+                "title": "Untitled",
+                "seats": 100,
+                "rank": -1,
+                "reference": next_code(),
+            },
+        },
+
+        Ticket {
+            type: "ticket",
+            primary_key: "code",
+            key_generation: Custom(next_code),
+            attributes: {
+                "code": String,
+            },
+            relationships: {},
+        }
+    }
+
+    fn next_code() -> Attribute {
+        static NEXT: AtomicUsize = ATOMIC_USIZE_INIT;
+        format!("T{}", NEXT.fetch_add(1, atomic::Ordering::SeqCst)).into()
+    }
+
     #[test]
     fn test() {
         let user_id = 1.into();
-        User.find(&user_id);
+        assert!(User.find(&user_id).is_ok());
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        let mut user = User.create();
+        assert!(!user.is_dirty());
+
+        user.set("first_name", "Alice".to_string().into());
+        user.set("first_name", "Bob".to_string().into());
+        assert!(user.is_dirty());
+        assert_eq!(user.changed_attributes(), vec!["first_name"]);
+        assert_eq!(user.changes()["first_name"], (&Attribute::String(None), &"Bob".to_string().into()));
+
+        user.reset();
+        assert!(!user.is_dirty());
+        assert_eq!(user.get("first_name"), Some(&Attribute::String(None)));
+
+        user.set("last_name", "Smith".to_string().into());
+        user.mark_clean();
+        user.set("last_name", "Jones".to_string().into());
+        user.set("last_name", "Smith".to_string().into());
+        assert!(!user.is_dirty());
+    }
+
+    #[test]
+    fn test_try_set() {
+        let mut user = User.create();
+        assert!(user.try_set("first_name", "Alice".to_string().into()).is_ok());
+        assert!(user.try_set("first_name", 1.into()).is_err());
+        assert!(user.try_set("nickname", "Al".to_string().into()).is_err());
+
+        let mut order = Order.create();
+        assert!(order.try_set("price", 1i16.into()).is_err());
+        assert!(order.try_set_coerced("price", 1i16.into()).is_ok());
+        assert_eq!(order.get("price"), Some(&1f32.into()));
+        assert!(order.try_set_coerced("price", 1.0f64.into()).is_err());
+    }
+
+    #[test]
+    fn test_typed_accessors() {
+        let mut order = Order.create();
+        match order.get_f32("price") {
+            Err(Error::NullAttribute(_)) => {},
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(order.get_as::<Option<f32>>("price").unwrap(), None);
+
+        order.set("price", 9.5f32.into());
+        assert_eq!(order.get_f32("price").unwrap(), 9.5);
+        assert_eq!(order.get_as::<Option<f32>>("price").unwrap(), Some(9.5));
+        match order.get_i32("price") {
+            Err(Error::TypeMismatch(_, AttributeType::I32, AttributeType::F32)) => {},
+            r => panic!("unexpected {:?}", r),
+        }
+        match order.get_string("name") {
+            Err(Error::UnknownAttribute(_)) => {},
+            r => panic!("unexpected {:?}", r),
+        }
+
+        assert!(order.get("price").unwrap().ty() == AttributeType::F32);
+        assert_eq!(f32::try_from(order.get("price").unwrap().clone()), Ok(9.5));
+        assert!(Attribute::I32(None).is_null());
+        assert!(i32::try_from(Attribute::I32(None)).is_err());
+    }
+
+    #[test]
+    fn test_include() {
+        let adapter = MemoryAdapter::new();
+        for &(id, name) in &[(1, "Alice"), (2, "Bob")] {
+            let mut row = Attributes::new();
+            row.insert("id".to_string(), id.into());
+            row.insert("first_name".to_string(), name.to_string().into());
+            adapter.insert(&User, &row).unwrap();
+        }
+        for &(id, customer_id) in &[(10, 1), (11, 1), (12, 2)] {
+            let mut row = Attributes::new();
+            row.insert("id".to_string(), id.into());
+            row.insert("customer_id".to_string(), customer_id.into());
+            adapter.insert(&Order, &row).unwrap();
+        }
+
+        let users = Query::new(&User).include(vec!["orders.customer"]).get(&adapter).unwrap();
+        let alice = users.first().unwrap();
+        let orders = users.get_many(alice, "orders");
+        assert_eq!(orders.len(), 2);
+        for order in orders {
+            let customer = users.get_one(order, "customer").unwrap();
+            assert_eq!(customer.identity(), alice.identity());
+        }
+        assert_eq!(users.into_records().len(), 2);
+
+        let orders = Query::new(&Order).include(vec!["customer"]).get(&adapter).unwrap();
+        let order = orders.first().unwrap();
+        assert_eq!(orders.get_one(order, "customer").unwrap().get_string("first_name").unwrap(), "Alice");
+
+        assert!(Query::new(&Order).include(vec!["items"]).get(&adapter).is_err());
+
+        fn assert_send<T: Send + Sync>(_: &T) {}
+        assert_send(&orders);
+    }
+
+    #[test]
+    fn test_composite_key() {
+        let adapter = MemoryAdapter::new();
+        adapter.insert(&Sensor, &Attributes::new()).unwrap();
+        for &(taken_at, value) in &[(100i64, 1.5f64), (200, 2.5)] {
+            let mut reading = Reading.create();
+            reading.set("sensor_id", 1.into());
+            reading.set("taken_at", taken_at.into());
+            reading.set("value", value.into());
+            assert_eq!(reading.key(), vec![1.into(), taken_at.into()]);
+            adapter.insert(&Reading, &reading.to_row()).unwrap();
+        }
+        let mut note = Attributes::new();
+        note.insert("reading_sensor_id".to_string(), 1.into());
+        note.insert("reading_taken_at".to_string(), 200i64.into());
+        adapter.insert(&Note, &note).unwrap();
+
+        let (sensor_id, taken_at) = (1.into(), 200i64.into());
+        let readings = Reading.find_by_key(vec![&sensor_id, &taken_at]).unwrap().include(vec!["sensor", "notes"]).get(&adapter).unwrap();
+        let reading = readings.first().unwrap();
+        assert_eq!(readings.len(), 1);
+        match Reading.find(&sensor_id) {
+            Err(Error::InvalidParameter(ref name)) if name == "key" => {},
+            _ => panic!(),
+        }
+        assert_eq!(adapter.delete(&Reading, &[1.into()]).unwrap(), 0);
+        assert_eq!(reading.identity(), ("reading", vec![1.into(), 200i64.into()]));
+        assert!(reading.id.is_null());
+        assert_eq!(readings.get_one(reading, "sensor").unwrap().id, 1.into());
+        assert_eq!(readings.get_many(reading, "notes").len(), 1);
+
+        let notes = Query::new(&Note).include(vec!["reading.sensor"]).get(&adapter).unwrap();
+        let reading = notes.get_one(notes.first().unwrap(), "reading").unwrap();
+        assert_eq!(reading.get_f64("value").unwrap(), 2.5);
+        assert!(notes.get_one(reading, "sensor").is_some());
+
+        let sensors = Query::new(&Sensor).include(vec!["readings"]).get(&adapter).unwrap();
+        assert_eq!(sensors.get_many(sensors.first().unwrap(), "readings").len(), 2);
+
+        let mut attributes = Attributes::new();
+        attributes.insert("value".to_string(), 3.5f64.into());
+        assert_eq!(adapter.update(&Reading, &[1.into(), 100i64.into()], &attributes).unwrap(), 1);
+        assert_eq!(adapter.delete(&Reading, &[1.into(), 200i64.into()]).unwrap(), 1);
+        let readings = Query::new(&Reading).get(&adapter).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings.first().unwrap().get_f64("value").unwrap(), 3.5);
+    }
+
+    #[test]
+    fn test_record_set() {
+        let records = [(1, 3.0f32), (2, 1.0), (3, 2.0), (4, 1.0)].iter().map(|&(id, price)| {
+            let mut order = Order.create();
+            order.id = id.into();
+            order.set("price", price.into());
+            order
+        }).collect();
+        let mut orders = RecordSet::new(records);
+        orders.records.push(Order.create());
+
+        assert_eq!(orders.len(), 4);
+        assert_eq!(orders.last().unwrap().id, 4.into());
+        assert_eq!(orders.get_by_id(&3.into()).unwrap().get_f32("price").unwrap(), 2.0);
+        assert_eq!(orders.pluck("price"), vec![&3.0f32.into(), &1.0f32.into(), &2.0f32.into(), &1.0f32.into()]);
+        assert_eq!((&orders).into_iter().count(), 4);
+
+        orders.sort_by("price", SortOrder::DESC);
+        let ids: Vec<Attribute> = orders.iter().map(|order| order.id.clone()).collect();
+        assert_eq!(ids, vec![1.into(), 3.into(), 2.into(), 4.into()]);
+        assert_eq!(orders.get(&("order", vec![2.into()])).unwrap().id, 2.into());
+
+        assert_eq!(orders.group_by("price")[&1.0f32.into()].len(), 2);
+        assert_eq!(orders.index_by("price")[&3.0f32.into()].id, 1.into());
+        assert_eq!(orders.into_iter().count(), 4);
+    }
+
+    #[test]
+    fn test_compare_nulls_last() {
+        use std::cmp::Ordering;
+
+        let one: Attribute = 1.into();
+        assert_eq!(Attribute::I32(None).compare(&one), Some(Ordering::Greater));
+        assert_eq!(one.compare(&2.into()), Some(Ordering::Less));
+        assert_eq!(one.compare(&"1".to_string().into()), None);
+    }
+
+    #[test]
+    fn test_insert_all() {
+        let adapter = MemoryAdapter::new();
+        let mut records = vec![];
+        for price in 0..3 {
+            let mut order = Order.create();
+            order.set("price", (price as f32).into());
+            records.push(order);
+        }
+        records[2].id = 10.into();
+
+        assert_eq!(Order.insert_all(&adapter, records).unwrap(), vec![vec![1.into()], vec![2.into()], vec![10.into()]]);
+        assert_eq!(adapter.rows(&Order).len(), 3);
+        assert!(Order.insert_all(&adapter, vec![User.create()]).is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let event = Event.create();
+        assert_eq!(event.get("title"), Some(&"Untitled".to_string().into()));
+        assert_eq!(event.get("seats"), Some(&100i64.into()));
+        assert_eq!(event.get("rank"), Some(&(-1).into()));
+        assert!(event.get_as::<String>("reference").unwrap().starts_with("T"));
+        assert!(event.is_changed("seats"));
+        assert!(event.is_defaulted("seats"));
+        assert!(Event.create().get("reference") != event.get("reference"));
+
+        let stored = Event.create_empty();
+        assert_eq!(stored.get("title"), Some(&Attribute::String(None)));
+        assert!(!stored.is_dirty());
+
+        let mut invalid = super::model("invalid", vec!["id".to_string()]);
+        invalid.attributes.insert("seats".to_string(), AttributeType::I32);
+        invalid.defaults.insert("seats".to_string(), DefaultValue::Value("many".to_string().into()));
+        match invalid.check_defaults() {
+            Err(Error::TypeMismatch(ref name, AttributeType::I32, AttributeType::String)) if name == "seats" => {},
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_key_generation() {
+        assert_eq!(User.create().id, Attribute::I32(None));
+        assert_eq!(Event.create().id, Attribute::I64(None));
+        assert_eq!(Reading.id_type(), None);
+        assert_eq!(User.generate_id(), None);
+
+        let adapter = MemoryAdapter::new();
+        assert_eq!(Event.insert_all(&adapter, vec![Event.create()]).unwrap(), vec![vec![1i64.into()]]);
+
+        let mut tickets = vec![Ticket.create(), Ticket.create()];
+        tickets[1].id = "VIP".to_string().into();
+        let keys = Ticket.insert_all(&adapter, tickets).unwrap();
+        assert_eq!(keys[0][0].ty(), AttributeType::String);
+        assert!(!keys[0][0].is_null());
+        assert_eq!(keys[1], vec!["VIP".to_string().into()]);
+    }
+
+    #[test]
+    fn test_check_path() {
+        assert!(User.check_path("orders.price", true).is_ok());
+        assert!(User.check_path("orders.customer.id", true).is_ok());
+        assert!(Order.check_path("customer.first_name", false).is_ok());
+
+        match User.check_path("orders.price", false) {
+            Err(Error::InvalidPath(ref path)) if path == "orders.price" => {},
+            _ => panic!(),
+        }
+        match User.check_path("invoices.price", true) {
+            Err(Error::UnknownRelationship(ref name)) if name == "invoices" => {},
+            _ => panic!(),
+        }
+        match Order.check_path("customer.age", true) {
+            Err(Error::UnknownAttribute(ref path)) if path == "customer.age" => {},
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_check_query_paths() {
+        let adapter = MemoryAdapter::new();
+        let sorted = || Query::new(&Order).order_by("customer.first_name", SortOrder::ASC);
+        let cursor = Cursor::new(vec!["Ann".to_string().into(), 1.into()]);
+        match sorted().after(cursor).get(&adapter) {
+            Err(Error::InvalidPath(ref path)) if path == "customer.first_name" => {},
+            _ => panic!(),
+        }
+        assert!(sorted().find_each(&adapter, 10).next().unwrap().is_err());
+
+        match Query::new(&Order).select(vec!["customer.first_name"]).get(&adapter) {
+            Err(Error::InvalidPath(ref path)) if path == "customer.first_name" => {},
+            _ => panic!(),
+        }
     }
 }
@@ -0,0 +1,252 @@
+use std::str::FromStr;
+use std::vec;
+
+use adapter::Adapter;
+use attribute::Attribute;
+use error::{Error, Result};
+use model::{Record, RecordSet};
+use query::Query;
+
+/// A page of results returned by `Query::paginate`, pages are numbered from 1.
+#[derive(Debug)]
+pub struct Page {
+    pub records: RecordSet,
+    pub page: i32,
+    pub per_page: i32,
+    pub total: u64,
+    pub total_pages: u64,
+    pub prev_page: Option<i32>,
+    pub next_page: Option<i32>,
+}
+
+impl Page {
+    pub fn new(records: RecordSet, page: i32, per_page: i32, total: u64) -> Page {
+        let total_pages = (total + per_page as u64 - 1) / per_page as u64;
+        Page {
+            records: records,
+            page: page,
+            per_page: per_page,
+            total: total,
+            total_pages: total_pages,
+            prev_page: if page > 1 { Some(page - 1) } else { None },
+            next_page: if (page as u64) < total_pages { Some(page + 1) } else { None },
+        }
+    }
+}
+
+/// Iterates over the results of a query in batches, see `Query::find_in_batches`.
+pub struct Batches<'a, 'b, A: 'b> {
+    query: Query<'a>,
+    adapter: &'b A,
+    size: i32,
+    cursor: Option<Cursor>,
+    done: bool,
+}
+
+impl<'a, 'b, A: Adapter> Batches<'a, 'b, A> {
+    pub fn new(query: Query<'a>, adapter: &'b A, size: i32) -> Batches<'a, 'b, A> {
+        Batches {
+            query: query,
+            adapter: adapter,
+            size: size,
+            cursor: None,
+            done: false,
+        }
+    }
+}
+
+impl<'a, 'b, A: Adapter> Iterator for Batches<'a, 'b, A> {
+    type Item = Result<RecordSet>;
+
+    fn next(&mut self) -> Option<Result<RecordSet>> {
+        if self.done {
+            return None;
+        }
+        if self.size < 1 {
+            self.done = true;
+            return Some(Err(Error::InvalidParameter("size".to_string())));
+        }
+        if let Err(err) = self.query.check_seekable() {
+            self.done = true;
+            return Some(Err(err));
+        }
+
+        let mut query = self.query.clone().limit(self.size);
+        if let Some(cursor) = self.cursor.take() {
+            query = query.after(cursor);
+        }
+
+        match query.get(self.adapter) {
+            Ok(mut records) => {
+                if records.is_empty() {
+                    self.done = true;
+                    return None;
+                }
+                self.cursor = records.next_cursor.take();
+                self.done = self.cursor.is_none();
+                Some(Ok(records))
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+/// Iterates over the results of a query one record at a time while loading them in batches, see
+/// `Query::find_each`.
+pub struct Records<'a, 'b, A: 'b> {
+    batches: Batches<'a, 'b, A>,
+    records: vec::IntoIter<Record>,
+}
+
+impl<'a, 'b, A: Adapter> Records<'a, 'b, A> {
+    pub fn new(batches: Batches<'a, 'b, A>) -> Records<'a, 'b, A> {
+        Records {
+            batches: batches,
+            records: vec![].into_iter(),
+        }
+    }
+}
+
+impl<'a, 'b, A: Adapter> Iterator for Records<'a, 'b, A> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some(Ok(record));
+            }
+
+            match self.batches.next() {
+                Some(Ok(records)) => self.records = records.into_iter(),
+                Some(Err(err)) => return Some(Err(err)),
+                None => return None,
+            }
+        }
+    }
+}
+
+/// The position of a record in a sorted query: the values of its sort keys followed by its primary
+/// key, see `Query::after` and `Query::before`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub values: Vec<Attribute>,
+}
+
+impl Cursor {
+    pub fn new(values: Vec<Attribute>) -> Cursor {
+        Cursor {
+            values: values,
+        }
+    }
+
+    /// Encodes the cursor as an opaque, URL safe string.
+    pub fn encode(&self) -> String {
+        self.values.iter().map(|value| {
+            let (tag, value) = match *value {
+                Attribute::Bool(ref v) => ('B', v.map(|v| v.to_string())),
+                Attribute::I8(ref v) => ('C', v.map(|v| v.to_string())),
+                Attribute::I16(ref v) => ('H', v.map(|v| v.to_string())),
+                Attribute::I32(ref v) => ('I', v.map(|v| v.to_string())),
+                Attribute::I64(ref v) => ('L', v.map(|v| v.to_string())),
+                Attribute::F32(ref v) => ('F', v.map(|v| v.to_string())),
+                Attribute::F64(ref v) => ('D', v.map(|v| v.to_string())),
+                Attribute::String(ref v) => ('S', v.clone()),
+            };
+            match value {
+                Some(value) => format!("{}{}", tag, value.bytes().map(|b| format!("{:02x}", b)).collect::<String>()),
+                None => format!("{}N", tag),
+            }
+        }).collect::<Vec<String>>().join(".")
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor> {
+        let mut values = vec![];
+        for value in cursor.split('.') {
+            values.push(try!(decode_value(value).ok_or_else(|| Error::InvalidCursor(cursor.to_string()))));
+        }
+        Ok(Cursor::new(values))
+    }
+}
+
+fn decode_value(value: &str) -> Option<Attribute> {
+    // Encoded cursors are ASCII, which also makes slicing them by byte safe
+    if value.len() == 0 || value.bytes().any(|b| b >= 0x80) {
+        return None;
+    }
+
+    let (tag, value) = value.split_at(1);
+    let value = if value == "N" {
+        None
+    } else {
+        Some(try_opt!(decode_hex(value)))
+    };
+
+    match tag {
+        "B" => parse(value).map(Attribute::Bool),
+        "C" => parse(value).map(Attribute::I8),
+        "H" => parse(value).map(Attribute::I16),
+        "I" => parse(value).map(Attribute::I32),
+        "L" => parse(value).map(Attribute::I64),
+        "F" => parse(value).map(Attribute::F32),
+        "D" => parse(value).map(Attribute::F64),
+        "S" => Some(Attribute::String(value)),
+        _ => None,
+    }
+}
+
+// Parses a nullable value, `None` stands for a malformed one.
+fn parse<T: FromStr>(value: Option<String>) -> Option<Option<T>> {
+    match value {
+        Some(value) => value.parse().ok().map(Some),
+        None => Some(None),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut bytes = vec![];
+    for i in 0..hex.len() / 2 {
+        bytes.push(try_opt!(u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()));
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use attribute::Attribute;
+    use model::RecordSet;
+
+    #[test]
+    fn test_page() {
+        let page = Page::new(RecordSet::new(vec![]), 1, 10, 25);
+        assert_eq!((page.total_pages, page.prev_page, page.next_page), (3, None, Some(2)));
+
+        let page = Page::new(RecordSet::new(vec![]), 3, 10, 25);
+        assert_eq!((page.total_pages, page.prev_page, page.next_page), (3, Some(2), None));
+
+        let page = Page::new(RecordSet::new(vec![]), 1, 10, 0);
+        assert_eq!((page.total_pages, page.prev_page, page.next_page), (0, None, None));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let cursor = Cursor::new(vec![1.5f32.into(), "a.b é".to_string().into(), Attribute::I64(None), true.into()]);
+        let encoded = cursor.encode();
+        assert!(encoded.chars().all(|c| c.is_alphanumeric() || c == '.'));
+        assert_eq!(Cursor::decode(&encoded).unwrap(), cursor);
+
+        assert!(Cursor::decode("").is_err());
+        assert!(Cursor::decode("I3").is_err());
+        assert!(Cursor::decode("X31").is_err());
+        assert!(Cursor::decode("I61").is_err());
+        assert!(Cursor::decode("é").is_err());
+        assert!(Cursor::decode("Sé1").is_err());
+    }
+}
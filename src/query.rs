@@ -1,31 +1,183 @@
+use std::cmp::Ordering;
+
 use adapter::Adapter;
-use attribute::Attribute;
-use model::{Model, RecordSet};
+use attribute::{Attribute, Attributes};
+use error::{Error, Result};
+use model::{Model, Record, RecordSet};
+use pagination::{Batches, Cursor, Page, Records};
+use serializer::{Serializer, SimpleSerializer};
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SortOrder {
     ASC,
     DESC,
 }
 
+impl SortOrder {
+    pub fn reverse(self) -> SortOrder {
+        match self {
+            SortOrder::ASC => SortOrder::DESC,
+            SortOrder::DESC => SortOrder::ASC,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Filter<'a> {
     IsNull(&'a str),
     IsNotNull(&'a str),
 
     Equal(&'a str, &'a Attribute),
     In(&'a str, Vec<&'a Attribute>),
+    GreaterThan(&'a str, &'a Attribute),
+    LessThan(&'a str, &'a Attribute),
+
+    /// The attribute is one of the values selected by the query: its first selected field, or its
+    /// primary key.
+    InQuery(&'a str, Box<Query<'a>>),
+    /// The query has results, its filter can refer to the enclosing query with `EqualOuter`.
+    Exists(Box<Query<'a>>),
+    /// The attribute of a subquery equals an attribute of the enclosing query.
+    EqualOuter(&'a str, &'a str),
 
     And(Box<Filter<'a>>, Box<Filter<'a>>),
     Or(Box<Filter<'a>>, Box<Filter<'a>>),
 }
 
+impl<'a> Filter<'a> {
+    /// Tests a record in memory the same way Postgres evaluates the filter in a `WHERE` clause,
+    /// i.e. with three-valued logic where comparing with null is unknown and unknown doesn't match.
+    ///
+    /// Attributes missing from the record and values of mismatched types compare as unknown (where
+    /// Postgres would fail the query instead). Subqueries can't be run in memory, so they are
+    /// unknown as well, see `check_local`.
+    pub fn matches(&self, record: &Record) -> bool {
+        self.eval(record) == Some(true)
+    }
+
+    /// Fails with `UnsupportedFilter` if the filter has subqueries, which `matches` can't evaluate.
+    pub fn check_local(&self) -> Result<()> {
+        match *self {
+            Filter::InQuery(..) | Filter::Exists(_) | Filter::EqualOuter(..) => Err(Error::UnsupportedFilter),
+            Filter::And(ref f1, ref f2) | Filter::Or(ref f1, ref f2) => {
+                try!(f1.check_local());
+                f2.check_local()
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// The attribute names (or dotted paths) the filter refers to, not counting those of subqueries.
+    pub fn names(&self) -> Vec<&'a str> {
+        match *self {
+            Filter::IsNull(name) | Filter::IsNotNull(name) | Filter::Equal(name, _) | Filter::In(name, _) |
+            Filter::GreaterThan(name, _) | Filter::LessThan(name, _) | Filter::InQuery(name, _) |
+            Filter::EqualOuter(name, _) => vec![name],
+            Filter::Exists(_) => vec![],
+            Filter::And(ref f1, ref f2) | Filter::Or(ref f1, ref f2) => {
+                let mut names = f1.names();
+                names.extend(f2.names());
+                names
+            },
+        }
+    }
+
+    /// The subqueries of the filter.
+    pub fn queries(&self) -> Vec<&Query<'a>> {
+        match *self {
+            Filter::InQuery(_, ref query) | Filter::Exists(ref query) => vec![&**query],
+            Filter::And(ref f1, ref f2) | Filter::Or(ref f1, ref f2) => {
+                let mut queries = f1.queries();
+                queries.extend(f2.queries());
+                queries
+            },
+            _ => vec![],
+        }
+    }
+
+    // `None` stands for SQL's unknown.
+    fn eval(&self, record: &Record) -> Option<bool> {
+        match *self {
+            Filter::IsNull(name) => record.value(name).map(|value| value.is_null()),
+            Filter::IsNotNull(name) => record.value(name).map(|value| !value.is_null()),
+            Filter::Equal(name, attribute) => record.value(name).and_then(|value| equals(value, attribute)),
+            Filter::In(name, ref attributes) => {
+                let value = try_opt!(record.value(name));
+                attributes.iter().fold(Some(false), |result, attribute| or(result, equals(value, attribute)))
+            },
+            Filter::GreaterThan(name, attribute) =>
+                record.value(name).and_then(|value| compare(value, attribute)).map(|o| o == Ordering::Greater),
+            Filter::LessThan(name, attribute) =>
+                record.value(name).and_then(|value| compare(value, attribute)).map(|o| o == Ordering::Less),
+            Filter::InQuery(..) | Filter::Exists(_) | Filter::EqualOuter(..) => None,
+            Filter::And(ref f1, ref f2) => and(f1.eval(record), f2.eval(record)),
+            Filter::Or(ref f1, ref f2) => or(f1.eval(record), f2.eval(record)),
+        }
+    }
+}
+
+fn equals(a: &Attribute, b: &Attribute) -> Option<bool> {
+    compare(a, b).map(|ordering| ordering == Ordering::Equal)
+}
+
+fn compare(a: &Attribute, b: &Attribute) -> Option<Ordering> {
+    if a.is_null() || b.is_null() {
+        None
+    } else {
+        a.compare(b)
+    }
+}
+
+fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }
+}
+
+fn or(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }
+}
+
+// The values of `name` sorted after `value`, `None` if there are none.
+fn seek_past<'b>(name: &'b str, order: SortOrder, value: &'b Attribute) -> Option<Filter<'b>> {
+    match (order, value.is_null()) {
+        (SortOrder::ASC, false) => Some(Filter::Or(Box::new(Filter::GreaterThan(name, value)),
+                                                   Box::new(Filter::IsNull(name)))),
+        (SortOrder::ASC, true) => None,
+        (SortOrder::DESC, false) => Some(Filter::LessThan(name, value)),
+        (SortOrder::DESC, true) => Some(Filter::IsNotNull(name)),
+    }
+}
+
+fn seek_equal<'b>(name: &'b str, value: &'b Attribute) -> Filter<'b> {
+    if value.is_null() {
+        Filter::IsNull(name)
+    } else {
+        Filter::Equal(name, value)
+    }
+}
+
+#[derive(Clone)]
 pub struct Query<'a> {
     pub model: Model,
-    pub include: Option<Vec<Model>>,
+    pub include: Option<Vec<&'a str>>,
     pub fields: Option<Vec<&'a str>>,
     pub sort: Option<Vec<(&'a str, SortOrder)>>,
     pub filter: Option<Filter<'a>>,
     pub offset: Option<i32>,
     pub limit: Option<i32>,
+    pub after: Option<Cursor>,
+    pub before: Option<Cursor>,
+    /// Paginates by cursor even without a cursor, see `keyset`.
+    pub keyset: bool,
+    /// Lets `update_all` and `delete_all` run without a filter.
+    pub unfiltered: bool,
 }
 
 impl<'a> Query<'a> {
@@ -38,15 +190,154 @@ impl<'a> Query<'a> {
             filter: None,
             offset: None,
             limit: None,
+            after: None,
+            before: None,
+            keyset: false,
+            unfiltered: false,
+        }
+    }
+
+    pub fn get<A: Adapter>(self, adapter: &A) -> Result<RecordSet> {
+        self.get_with(adapter, &SimpleSerializer)
+    }
+
+    /// Like `get`, with the records (including the related ones) built by `serializer`.
+    pub fn get_with<A: Adapter, S: Serializer>(self, adapter: &A, serializer: &S) -> Result<RecordSet> {
+        try!(self.check_paths());
+        for cursor in self.after.iter().chain(self.before.iter()) {
+            if cursor.values.len() != self.ordering().len() {
+                return Err(Error::InvalidCursor(cursor.encode()));
+            }
+        }
+
+        let mut record_set = if self.is_keyset() && self.limit.is_some() {
+            // One more record tells whether there is a page beyond this one
+            let mut query = self.clone();
+            query.limit = self.limit.map(|limit| limit.saturating_add(1));
+            try!(adapter.query_with(&query, serializer))
+        } else {
+            try!(adapter.query_with(&self, serializer))
+        };
+
+        if self.is_keyset() {
+            let more = match self.limit {
+                Some(limit) if record_set.len() as i32 > limit => {
+                    record_set.truncate(limit as usize);
+                    true
+                },
+                _ => false,
+            };
+            if self.before.is_some() {
+                record_set.reverse();
+            }
+
+            let (has_prev, has_next) = if self.before.is_some() {
+                (more, true)
+            } else {
+                (self.after.is_some(), more)
+            };
+            if has_prev {
+                record_set.prev_cursor = record_set.first().and_then(|record| self.cursor_of(record));
+            }
+            if has_next {
+                record_set.next_cursor = record_set.last().and_then(|record| self.cursor_of(record));
+            }
+        }
+
+        if let Some(ref include) = self.include {
+            for path in include {
+                try!(record_set.load(adapter, serializer, self.model, path));
+            }
+        }
+        Ok(record_set)
+    }
+
+    /// Counts the matching records, ignoring sorting, offset and limit.
+    pub fn count<A: Adapter>(&self, adapter: &A) -> Result<u64> {
+        try!(self.check_paths());
+        adapter.count(self)
+    }
+
+    /// Sets `attributes` on every matching record without loading them, ignoring sorting, offset
+    /// and limit. Returns the number of updated records.
+    pub fn update_all<A: Adapter>(&self, adapter: &A, attributes: &Attributes) -> Result<u64> {
+        try!(self.check_writable());
+        for (name, attribute) in attributes {
+            match self.model.attributes.get(name.as_str()) {
+                Some(&ty) if ty == attribute.ty() => {},
+                Some(&ty) => return Err(Error::TypeMismatch(name.clone(), ty, attribute.ty())),
+                None => return Err(Error::UnknownAttribute(name.clone())),
+            }
         }
+        if attributes.len() == 0 {
+            return Ok(0);
+        }
+        adapter.update_all(self, attributes)
+    }
+
+    /// Deletes every matching record without loading them, ignoring sorting, offset and limit.
+    /// Returns the number of deleted records.
+    pub fn delete_all<A: Adapter>(&self, adapter: &A) -> Result<u64> {
+        try!(self.check_writable());
+        adapter.delete_all(self)
+    }
+
+    /// Fetches page `page` (counting from 1) of `per_page` records, along with the total number of
+    /// matching records.
+    pub fn paginate<A: Adapter>(self, adapter: &A, page: i32, per_page: i32) -> Result<Page> {
+        if page < 1 {
+            return Err(Error::InvalidParameter("page".to_string()));
+        }
+        if per_page < 1 {
+            return Err(Error::InvalidParameter("per_page".to_string()));
+        }
+
+        let offset = match (page - 1).checked_mul(per_page) {
+            Some(offset) => offset,
+            None => return Err(Error::InvalidParameter("page".to_string())),
+        };
+
+        let total = try!(self.count(adapter));
+        let records = try!(self.offset(offset).limit(per_page).get(adapter));
+        Ok(Page::new(records, page, per_page, total))
+    }
+
+    /// Iterates over the results in batches of `size` records, seeking from one batch to the next
+    /// by cursor so memory use stays bounded even for large tables. The results are sorted by
+    /// primary key unless `order_by` was given (without dotted paths), `offset` and `limit` are
+    /// ignored. A `size` below 1 yields an `InvalidParameter` error.
+    pub fn find_in_batches<'b, A: Adapter>(self, adapter: &'b A, size: i32) -> Batches<'a, 'b, A> {
+        let mut query = self;
+        if query.sort.is_none() {
+            let model = query.model;
+            for name in &model.primary_key {
+                query = query.order_by(name, SortOrder::ASC);
+            }
+        }
+
+        // The sort keys have to be selected to seek past the last record of a batch
+        let ordering = query.ordering();
+        if let Some(ref mut fields) = query.fields {
+            for (name, _) in ordering {
+                if !fields.contains(&name) {
+                    fields.push(name);
+                }
+            }
+        }
+
+        query.offset = None;
+        Batches::new(query.keyset(), adapter, size)
     }
 
-    pub fn get<A: Adapter>(self, adapter: &A) -> Option<RecordSet> {
-        adapter.query(&self)
+    /// Like `find_in_batches`, but yields the records one by one. Related records are not
+    /// available, use `find_in_batches` along with `include` for those.
+    pub fn find_each<'b, A: Adapter>(self, adapter: &'b A, size: i32) -> Records<'a, 'b, A> {
+        Records::new(self.find_in_batches(adapter, size))
     }
 
-    pub fn include(mut self, models: Vec<Model>) -> Query<'a> {
-        self.include = Some(models);
+    /// Eager loads the related records along relationship paths like `"orders.customer"`.
+    pub fn include(mut self, paths: Vec<&'a str>) -> Query<'a> {
+        self.include = Some(paths);
         self
     }
 
@@ -66,6 +357,12 @@ impl<'a> Query<'a> {
         self
     }
 
+    /// Allows `update_all` and `delete_all` to touch every record when there is no filter.
+    pub fn unfiltered(mut self) -> Query<'a> {
+        self.unfiltered = true;
+        self
+    }
+
     pub fn offset(mut self, offset: i32) -> Query<'a> {
         self.offset = Some(offset);
         self
@@ -76,6 +373,167 @@ impl<'a> Query<'a> {
         self
     }
 
+    /// Paginates by cursor from the first page on: the primary key is added to the sort keys and,
+    /// with `limit`, the results carry the cursor of the next page. Implied by `after` and `before`.
+    pub fn keyset(mut self) -> Query<'a> {
+        self.keyset = true;
+        self
+    }
+
+    /// Seeks to the records following `cursor` in the order given by `order_by`. Usually combined
+    /// with `limit`, in which case the results carry the cursors of the neighbouring pages.
+    pub fn after(mut self, cursor: Cursor) -> Query<'a> {
+        self.after = Some(cursor);
+        self.before = None;
+        self
+    }
+
+    /// Seeks to the records preceding `cursor` in the order given by `order_by`.
+    pub fn before(mut self, cursor: Cursor) -> Query<'a> {
+        self.before = Some(cursor);
+        self.after = None;
+        self
+    }
+
+    /// Applies the JSON:API cursor pagination parameters `page[size]`, `page[after]` and
+    /// `page[before]`. Other parameters are ignored.
+    pub fn page_params<I, K, V>(self, params: I) -> Result<Query<'a>>
+        where I: IntoIterator<Item=(K, V)>, K: AsRef<str>, V: AsRef<str>
+    {
+        let mut query = self;
+        for (name, value) in params {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            query = match name {
+                "page[size]" => query.limit(try!(value.parse().map_err(|_| Error::InvalidParameter(name.to_string()))))
+                                     .keyset(),
+                "page[after]" => query.after(try!(Cursor::decode(value))),
+                "page[before]" => query.before(try!(Cursor::decode(value))),
+                _ => query,
+            };
+        }
+        Ok(query)
+    }
+
+    /// Returns the sort keys the adapter should order by. When paginating by cursor, the primary
+    /// key is added to make the order total, and a `before` cursor reverses the order (the results
+    /// are put back in order by `get`).
+    pub fn ordering(&self) -> Vec<(&'a str, SortOrder)> {
+        let mut ordering = self.sort.clone().unwrap_or_else(Vec::new);
+        if self.is_keyset() {
+            let model = self.model;
+            for name in &model.primary_key {
+                if !ordering.iter().any(|&(sorted, _)| sorted == name) {
+                    ordering.push((name, SortOrder::ASC));
+                }
+            }
+            if self.before.is_some() {
+                ordering = ordering.into_iter().map(|(name, order)| (name, order.reverse())).collect();
+            }
+        }
+        ordering
+    }
+
+    /// Returns the filter the adapter should apply, including the seek condition of a cursor.
+    pub fn condition<'b>(&'b self) -> Option<Filter<'b>> {
+        let seek = self.seek_filter();
+        match (self.filter.clone(), seek) {
+            (Some(filter), Some(seek)) => Some(Filter::And(Box::new(filter), Box::new(seek))),
+            (filter, None) => filter,
+            (None, seek) => seek,
+        }
+    }
+
+    /// Whether filters or sort keys refer to related models through dotted paths.
+    pub fn has_paths(&self) -> bool {
+        self.filter.iter().flat_map(|filter| filter.names()).chain(self.sort.iter().flat_map(|sort| {
+            sort.iter().map(|&(name, _)| name)
+        })).any(|name| name.contains('.'))
+    }
+
+    /// Cursors hold the values of the sort keys, which aren't loaded from related models, so a
+    /// query paginated by cursor can't be sorted by a dotted path.
+    pub fn check_seekable(&self) -> Result<()> {
+        for &(name, _) in self.sort.iter().flat_map(|sort| sort.iter()) {
+            if name.contains('.') {
+                return Err(Error::InvalidPath(name.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // Filters may follow any relationship, while sorting by an attribute of a `HasMany`
+    // relationship is ambiguous. Records only hold the attributes of their own model, so fields
+    // can't follow relationships.
+    fn check_paths(&self) -> Result<()> {
+        for name in self.filter.iter().flat_map(|filter| filter.names()) {
+            if name.contains('.') {
+                try!(self.model.check_path(name, true));
+            }
+        }
+        for &(name, _) in self.sort.iter().flat_map(|sort| sort.iter()) {
+            if name.contains('.') {
+                try!(self.model.check_path(name, false));
+            }
+        }
+        for &name in self.fields.iter().flat_map(|fields| fields.iter()) {
+            if name.contains('.') {
+                return Err(Error::InvalidPath(name.to_string()));
+            }
+        }
+        if self.is_keyset() {
+            try!(self.check_seekable());
+        }
+        for query in self.filter.iter().flat_map(|filter| filter.queries()) {
+            try!(query.check_paths());
+        }
+        Ok(())
+    }
+
+    // Updating or deleting a whole table by accident is refused.
+    fn check_writable(&self) -> Result<()> {
+        if self.filter.is_none() && !self.unfiltered {
+            return Err(Error::MissingFilter);
+        }
+        self.check_paths()
+    }
+
+    fn is_keyset(&self) -> bool {
+        self.keyset || self.after.is_some() || self.before.is_some()
+    }
+
+    fn cursor_of(&self, record: &Record) -> Option<Cursor> {
+        let mut values = vec![];
+        for (name, _) in self.ordering() {
+            values.push(try_opt!(record.value(name)).clone());
+        }
+        Some(Cursor::new(values))
+    }
+
+    // For sort keys `k1, k2, .., kn` and cursor values `v1, v2, .., vn` this is
+    // `k1 > v1 OR (k1 = v1 AND k2 > v2) OR ..`, with `<` for the keys sorted in descending order.
+    // Nulls sort as the largest values (as in Postgres), so they follow a non-null value sorted in
+    // ascending order, and nothing follows a null one.
+    fn seek_filter<'b>(&'b self) -> Option<Filter<'b>> {
+        let cursor = try_opt!(self.after.as_ref().or(self.before.as_ref()));
+        let ordering = self.ordering();
+
+        let mut seek: Option<Filter<'b>> = None;
+        for (i, (&(name, order), value)) in ordering.iter().zip(cursor.values.iter()).enumerate() {
+            let mut term = match seek_past(name, order, value) {
+                Some(term) => term,
+                None => continue,
+            };
+            for (&(name, _), value) in ordering[..i].iter().zip(cursor.values.iter()).rev() {
+                term = Filter::And(Box::new(seek_equal(name, value)), Box::new(term));
+            }
+            seek = Some(match seek {
+                Some(seek) => Filter::Or(Box::new(seek), Box::new(term)),
+                None => term,
+            });
+        }
+        seek
+    }
+
     pub fn where_(self, name: &'a str) -> WhereFilterBuilder<'a> {
         WhereFilterBuilder {
             query: self,
@@ -96,6 +554,23 @@ impl<'a> Query<'a> {
             name: name,
         }
     }
+
+    pub fn where_exists(mut self, query: Query<'a>) -> Query<'a> {
+        self.filter = Some(Filter::Exists(Box::new(query)));
+        self
+    }
+
+    pub fn and_exists(mut self, query: Query<'a>) -> Query<'a> {
+        self.filter = Some(Filter::And(Box::new(self.filter.unwrap()),
+                                       Box::new(Filter::Exists(Box::new(query)))));
+        self
+    }
+
+    pub fn or_exists(mut self, query: Query<'a>) -> Query<'a> {
+        self.filter = Some(Filter::Or(Box::new(self.filter.unwrap()),
+                                      Box::new(Filter::Exists(Box::new(query)))));
+        self
+    }
 }
 
 pub struct WhereFilterBuilder<'a> {
@@ -123,6 +598,26 @@ impl<'a> WhereFilterBuilder<'a> {
         self.query.filter = Some(Filter::In(self.name, attributes));
         self.query
     }
+
+    pub fn gt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::GreaterThan(self.name, attribute));
+        self.query
+    }
+
+    pub fn lt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::LessThan(self.name, attribute));
+        self.query
+    }
+
+    pub fn in_query(mut self, query: Query<'a>) -> Query<'a> {
+        self.query.filter = Some(Filter::InQuery(self.name, Box::new(query)));
+        self.query
+    }
+
+    pub fn eq_outer(mut self, name: &'a str) -> Query<'a> {
+        self.query.filter = Some(Filter::EqualOuter(self.name, name));
+        self.query
+    }
 }
 
 pub struct AndFilterBuilder<'a> {
@@ -154,6 +649,30 @@ impl<'a> AndFilterBuilder<'a> {
                                              Box::new(Filter::In(self.name, attributes))));
         self.query
     }
+
+    pub fn gt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::And(Box::new(self.query.filter.unwrap()),
+                                             Box::new(Filter::GreaterThan(self.name, attribute))));
+        self.query
+    }
+
+    pub fn lt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::And(Box::new(self.query.filter.unwrap()),
+                                             Box::new(Filter::LessThan(self.name, attribute))));
+        self.query
+    }
+
+    pub fn in_query(mut self, query: Query<'a>) -> Query<'a> {
+        self.query.filter = Some(Filter::And(Box::new(self.query.filter.unwrap()),
+                                             Box::new(Filter::InQuery(self.name, Box::new(query)))));
+        self.query
+    }
+
+    pub fn eq_outer(mut self, name: &'a str) -> Query<'a> {
+        self.query.filter = Some(Filter::And(Box::new(self.query.filter.unwrap()),
+                                             Box::new(Filter::EqualOuter(self.name, name))));
+        self.query
+    }
 }
 
 pub struct OrFilterBuilder<'a> {
@@ -185,4 +704,251 @@ impl<'a> OrFilterBuilder<'a> {
                                             Box::new(Filter::In(self.name, attributes))));
         self.query
     }
+
+    pub fn gt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::Or(Box::new(self.query.filter.unwrap()),
+                                            Box::new(Filter::GreaterThan(self.name, attribute))));
+        self.query
+    }
+
+    pub fn lt(mut self, attribute: &'a Attribute) -> Query<'a> {
+        self.query.filter = Some(Filter::Or(Box::new(self.query.filter.unwrap()),
+                                            Box::new(Filter::LessThan(self.name, attribute))));
+        self.query
+    }
+
+    pub fn in_query(mut self, query: Query<'a>) -> Query<'a> {
+        self.query.filter = Some(Filter::Or(Box::new(self.query.filter.unwrap()),
+                                            Box::new(Filter::InQuery(self.name, Box::new(query)))));
+        self.query
+    }
+
+    pub fn eq_outer(mut self, name: &'a str) -> Query<'a> {
+        self.query.filter = Some(Filter::Or(Box::new(self.query.filter.unwrap()),
+                                            Box::new(Filter::EqualOuter(self.name, name))));
+        self.query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adapter::Adapter;
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::{Attribute, Attributes, AttributeType};
+    use error::Error;
+    use model::Record;
+
+    model! {
+        Item {
+            type: "item",
+            attributes: {
+                "name": String,
+                "n": I32,
+            },
+            relationships: {},
+        }
+    }
+
+    fn item(n: Option<i32>) -> Record {
+        let mut item = Item.create();
+        item.id = 1.into();
+        item.set("n", Attribute::I32(n));
+        item
+    }
+
+    #[test]
+    fn test_matches_null_semantics() {
+        let one = 1.into();
+        let two = 2.into();
+        let null = Attribute::I32(None);
+
+        assert!(Filter::IsNull("n").matches(&item(None)));
+        assert!(!Filter::IsNotNull("n").matches(&item(None)));
+        assert!(Filter::Equal("n", &one).matches(&item(Some(1))));
+        assert!(Filter::Equal("id", &one).matches(&item(None)));
+        assert!(!Filter::Equal("n", &null).matches(&item(None)));
+        assert!(!Filter::Equal("n", &one).matches(&item(None)));
+        assert!(!Filter::Equal("name", &one).matches(&item(Some(1))));
+        assert!(!Filter::Equal("missing", &one).matches(&item(Some(1))));
+
+        assert!(Filter::In("n", vec![&null, &one]).matches(&item(Some(1))));
+        assert!(!Filter::In("n", vec![&null, &two]).matches(&item(Some(1))));
+        assert!(!Filter::In("n", vec![]).matches(&item(Some(1))));
+
+        // NOT is not expressible yet, so unknown and false can only be told apart through OR
+        let unknown = || Box::new(Filter::Equal("n", &null));
+        let is_true = || Box::new(Filter::IsNotNull("id"));
+        let is_false = || Box::new(Filter::IsNull("id"));
+        assert!(!Filter::And(unknown(), is_true()).matches(&item(Some(1))));
+        assert!(!Filter::And(is_false(), unknown()).matches(&item(Some(1))));
+        assert!(Filter::Or(unknown(), is_true()).matches(&item(Some(1))));
+        assert!(!Filter::Or(unknown(), is_false()).matches(&item(Some(1))));
+    }
+
+    #[test]
+    fn test_record_set_filter() {
+        let one = 1.into();
+        let items = RecordSet::new(vec![item(Some(1)), item(Some(2)), item(None)].into_iter().enumerate().map(|(i, mut item)| {
+            item.id = (i as i32).into();
+            item
+        }).collect());
+
+        assert_eq!(items.filter(&Filter::Equal("n", &one)).unwrap().len(), 1);
+        assert_eq!(items.filter(&Filter::IsNotNull("n")).unwrap().len(), 2);
+
+        let subquery = Filter::InQuery("n", Box::new(Query::new(&Item).select(vec!["n"])));
+        match items.filter(&Filter::Or(Box::new(Filter::IsNull("n")), Box::new(subquery))) {
+            Err(Error::UnsupportedFilter) => {},
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_keyset_pagination() {
+        let adapter = MemoryAdapter::new();
+        for &n in &[3, 1, 2, 1, 3] {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), n.into());
+            adapter.insert(&Item, &row).unwrap();
+        }
+
+        let page = |query: Query| -> (Vec<Attribute>, Option<Cursor>, Option<Cursor>) {
+            let items = query.order_by("n", SortOrder::DESC).limit(2).get(&adapter).unwrap();
+            (items.pluck("id").into_iter().cloned().collect(), items.prev_cursor.clone(), items.next_cursor.clone())
+        };
+
+        let (ids, prev, next) = page(Query::new(&Item));
+        assert_eq!(ids, vec![1.into(), 5.into()]);
+        assert!(prev.is_none() && next.is_none());
+
+        let (ids, prev, next) = page(Query::new(&Item).keyset());
+        assert_eq!(ids, vec![1.into(), 5.into()]);
+        assert!(prev.is_none());
+
+        let (ids, prev, next) = page(Query::new(&Item).after(next.unwrap()));
+        assert_eq!(ids, vec![3.into(), 2.into()]);
+
+        let (ids, _, next) = page(Query::new(&Item).after(next.unwrap()));
+        assert_eq!(ids, vec![4.into()]);
+        assert!(next.is_none());
+
+        let (ids, prev, _) = page(Query::new(&Item).before(prev.unwrap()));
+        assert_eq!(ids, vec![1.into(), 5.into()]);
+        assert!(prev.is_none());
+
+        let params = vec![("page[size]", "1"), ("page[after]", "I31")];
+        let query = Query::new(&Item).page_params(params).unwrap();
+        assert_eq!(query.limit, Some(1));
+        assert_eq!(query.after, Some(Cursor::new(vec![1.into()])));
+        assert!(Query::new(&Item).page_params(vec![("page[size]", "1")]).unwrap().keyset);
+        assert!(query.order_by("n", SortOrder::ASC).get(&adapter).is_err());
+        assert!(Query::new(&Item).page_params(vec![("page[size]", "x")]).is_err());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let adapter = MemoryAdapter::new();
+        for n in 0..5 {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), (n % 2).into());
+            adapter.insert(&Item, &row).unwrap();
+        }
+
+        let zero = 0.into();
+        let page = Query::new(&Item).where_("n").eq(&zero).paginate(&adapter, 2, 2).unwrap();
+        assert_eq!(page.records.pluck("id"), vec![&5.into()]);
+        assert_eq!((page.total, page.total_pages, page.prev_page, page.next_page), (3, 2, Some(1), None));
+
+        assert!(Query::new(&Item).paginate(&adapter, 0, 2).is_err());
+        assert!(Query::new(&Item).paginate(&adapter, 1, 0).is_err());
+        assert!(Query::new(&Item).paginate(&adapter, ::std::i32::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn test_update_and_delete_all() {
+        let adapter = MemoryAdapter::new();
+        for n in 0..4 {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), n.into());
+            adapter.insert(&Item, &row).unwrap();
+        }
+
+        let two = 2.into();
+        let mut attributes = Attributes::new();
+        attributes.insert("name".to_string(), "small".to_string().into());
+        assert_eq!(Query::new(&Item).where_("n").lt(&two).update_all(&adapter, &attributes).unwrap(), 2);
+        assert_eq!(Query::new(&Item).where_("name").is_not_null().count(&adapter).unwrap(), 2);
+
+        attributes.insert("n".to_string(), "two".to_string().into());
+        match Query::new(&Item).where_("n").eq(&two).update_all(&adapter, &attributes) {
+            Err(Error::TypeMismatch(ref name, AttributeType::I32, AttributeType::String)) if name == "n" => {},
+            _ => panic!(),
+        }
+
+        assert_eq!(Query::new(&Item).where_("name").is_null().delete_all(&adapter).unwrap(), 2);
+        match Query::new(&Item).delete_all(&adapter) {
+            Err(Error::MissingFilter) => {},
+            _ => panic!(),
+        }
+        let exists = || Query::new(&Item).where_exists(Query::new(&Item).where_("n").eq(&two));
+        match exists().delete_all(&adapter) {
+            Err(Error::UnsupportedFilter) => {},
+            _ => panic!(),
+        }
+        assert!(exists().get(&adapter).is_err());
+        assert_eq!(Query::new(&Item).unfiltered().delete_all(&adapter).unwrap(), 2);
+        assert_eq!(adapter.rows(&Item).len(), 0);
+    }
+
+    #[test]
+    fn test_find_in_batches() {
+        let adapter = MemoryAdapter::new();
+        for n in 0..5 {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), n.into());
+            adapter.insert(&Item, &row).unwrap();
+        }
+
+        let sizes: Vec<usize> = Query::new(&Item).select(vec!["n"])
+                                                 .find_in_batches(&adapter, 2)
+                                                 .map(|batch| batch.unwrap().len())
+                                                 .collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        let ns: Vec<Attribute> = Query::new(&Item).order_by("n", SortOrder::DESC)
+                                                  .find_each(&adapter, 2)
+                                                  .map(|item| item.unwrap().get("n").unwrap().clone())
+                                                  .collect();
+        assert_eq!(ns, vec![4.into(), 3.into(), 2.into(), 1.into(), 0.into()]);
+        assert_eq!(adapter.queries.get(), 6);
+
+        let mut batches = Query::new(&Item).find_in_batches(&adapter, 0);
+        match batches.next() {
+            Some(Err(Error::InvalidParameter(ref name))) if name == "size" => {},
+            _ => panic!(),
+        }
+        assert!(batches.next().is_none());
+        assert!(Query::new(&Item).find_each(&adapter, -1).next().unwrap().is_err());
+        assert_eq!(adapter.queries.get(), 6);
+    }
+
+    #[test]
+    fn test_find_each_with_nulls() {
+        let adapter = MemoryAdapter::new();
+        for n in vec![Some(1), None, Some(2), None, Some(3)] {
+            let mut row = Attributes::new();
+            row.insert("n".to_string(), Attribute::I32(n));
+            adapter.insert(&Item, &row).unwrap();
+        }
+
+        let ids = |order| -> Vec<Attribute> {
+            Query::new(&Item).order_by("n", order)
+                             .find_each(&adapter, 2)
+                             .map(|item| item.unwrap().id.clone())
+                             .collect()
+        };
+        assert_eq!(ids(SortOrder::ASC), vec![1.into(), 3.into(), 5.into(), 2.into(), 4.into()]);
+        assert_eq!(ids(SortOrder::DESC), vec![2.into(), 4.into(), 5.into(), 3.into(), 1.into()]);
+    }
 }
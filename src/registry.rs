@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use error::{Error, Result};
+use model::{Model, ModelDef};
+use relationship::RelationshipType;
+
+/// Maps type strings back to the models declared with `model!`, e.g. to find the model of a
+/// `Record` or of a JSON:API payload.
+pub struct Registry {
+    models: BTreeMap<&'static str, Model>,
+}
+
+fn same(a: Model, b: Model) -> bool {
+    a as *const ModelDef == b as *const ModelDef
+}
+
+fn target(relationship: &RelationshipType) -> Model {
+    match *relationship {
+        RelationshipType::BelongsTo(target) |
+        RelationshipType::HasOne(target) |
+        RelationshipType::HasMany(target) => target,
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            models: BTreeMap::new(),
+        }
+    }
+
+    /// Registers `model` along with every model it is related to, directly or not. Registering
+    /// another model under a type string already taken is an error, in which case none of the
+    /// models are registered.
+    pub fn register(&mut self, model: Model) -> Result<()> {
+        let mut found: BTreeMap<&'static str, Model> = BTreeMap::new();
+        let mut pending = vec![model];
+        while let Some(model) = pending.pop() {
+            match found.get(&model.ty[..]).or_else(|| self.models.get(&model.ty[..])) {
+                Some(&other) if same(other, model) => continue,
+                Some(_) => return Err(Error::DuplicateModel(model.ty.to_string())),
+                None => {},
+            }
+            found.insert(&model.ty, model);
+            pending.extend(model.relationships.values().map(target));
+        }
+        self.models.extend(found);
+        Ok(())
+    }
+
+    /// The registered models, ordered by type.
+    pub fn models(&self) -> Vec<Model> {
+        self.models.values().cloned().collect()
+    }
+
+    pub fn get(&self, ty: &str) -> Option<Model> {
+        self.models.get(ty).cloned()
+    }
+
+    pub fn find(&self, ty: &str) -> Result<Model> {
+        self.get(ty).ok_or_else(|| Error::UnknownModel(ty.to_string()))
+    }
+
+    /// The model at the end of the dotted relationship path `path`, e.g. `"orders.items"`, starting
+    /// from the model of type `ty`.
+    pub fn related(&self, ty: &str, path: &str) -> Result<Model> {
+        let mut model = try!(self.find(ty));
+        for name in path.split('.') {
+            model = try!(model.relationships.get(name).map(target)
+                                                .ok_or_else(|| Error::UnknownRelationship(name.to_string())));
+        }
+        Ok(model)
+    }
+
+    /// The relationships of the registered models pointing at `model`, as `(model, name)` pairs
+    /// ordered by type and name.
+    pub fn referencing(&self, model: Model) -> Vec<(Model, &'static str)> {
+        let mut references = vec![];
+        for &other in self.models.values() {
+            let mut names: Vec<&'static str> = other.relationships.iter()
+                                                    .filter(|&(_, relationship)| same(target(relationship), model))
+                                                    .map(|(name, _)| &name[..])
+                                                    .collect();
+            names.sort();
+            references.extend(names.into_iter().map(|name| (other, name)));
+        }
+        references
+    }
+
+    /// The registered models ordered so that each comes after the models its `BelongsTo`
+    /// relationships point at, e.g. to create their tables. Models in a cycle are ordered by type.
+    pub fn dependency_order(&self) -> Vec<Model> {
+        let mut ordered: Vec<Model> = vec![];
+        let mut remaining = self.models();
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|&model| {
+                model.relationships.values().all(|relationship| match *relationship {
+                    RelationshipType::BelongsTo(parent) => {
+                        same(parent, model) || ordered.iter().any(|&other| same(other, parent))
+                    },
+                    _ => true,
+                })
+            });
+            ordered.push(remaining.remove(ready.unwrap_or(0)));
+        }
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+
+    model! {
+        Customer {
+            type: "customer",
+            attributes: {
+                "name": String,
+            },
+            relationships: {
+                "orders": HasMany<Order>,
+            },
+        },
+
+        Order {
+            type: "order",
+            attributes: {
+                "price": F32,
+            },
+            relationships: {
+                "customer": BelongsTo<Customer>,
+                "items": HasMany<Item>,
+            },
+        },
+
+        Item {
+            type: "item",
+            attributes: {},
+            relationships: {
+                "order": BelongsTo<Order>,
+            },
+        },
+
+        OtherOrder {
+            type: "order",
+            attributes: {},
+            relationships: {},
+        },
+
+        Invoice {
+            type: "invoice",
+            attributes: {},
+            relationships: {
+                "order": BelongsTo<OtherOrder>,
+            },
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new();
+        registry.register(&Order).unwrap();
+        registry.register(&Customer).unwrap();
+
+        let types: Vec<&str> = registry.models().iter().map(|model| &model.ty[..]).collect();
+        assert_eq!(types, vec!["customer", "item", "order"]);
+        assert_eq!(registry.find("order").unwrap().ty, "order");
+        assert!(registry.get("user").is_none());
+
+        assert_eq!(registry.related("customer", "orders.items").unwrap().ty, "item");
+        match registry.related("customer", "orders.product") {
+            Err(Error::UnknownRelationship(ref name)) if name == "product" => {},
+            _ => panic!(),
+        }
+
+        let references: Vec<(&str, &str)> = registry.referencing(&Order).iter().map(|&(model, name)| (&model.ty[..], name)).collect();
+        assert_eq!(references, vec![("customer", "orders"), ("item", "order")]);
+
+        let order: Vec<&str> = registry.dependency_order().iter().map(|model| &model.ty[..]).collect();
+        assert_eq!(order, vec!["customer", "order", "item"]);
+
+        match registry.register(&OtherOrder) {
+            Err(Error::DuplicateModel(ref ty)) if ty == "order" => {},
+            _ => panic!(),
+        }
+        match registry.register(&Invoice) {
+            Err(Error::DuplicateModel(ref ty)) if ty == "order" => {},
+            _ => panic!(),
+        }
+        assert!(registry.get("invoice").is_none());
+    }
+}
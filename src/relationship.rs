@@ -1,9 +1,64 @@
-use std::collections::HashMap;
+use std::cell::UnsafeCell;
+use std::collections::{hash_map, HashMap};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 
-use model::{Model, Record};
+use model::{Identity, Model};
 
-pub type RelationshipTypes = HashMap<&'static str, RelationshipType>;
-pub type Relationships<'a> = HashMap<String, Relationship<'a>>;
+pub type Relationships = HashMap<String, Relationship>;
+
+/// The relationships declared by a model, by name. Models may refer to each other, so those built
+/// at runtime (see `schema`) get their relationships once they have all been allocated, see
+/// `init`.
+pub struct RelationshipTypes {
+    types: UnsafeCell<HashMap<String, RelationshipType>>,
+}
+
+// Only `init` writes through a shared reference, before the model is handed out
+unsafe impl Sync for RelationshipTypes {}
+
+impl RelationshipTypes {
+    pub fn new() -> RelationshipTypes {
+        RelationshipTypes {
+            types: UnsafeCell::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the relationships of a model which other models may already refer to. Unsafe as
+    /// nothing may read them meanwhile: it is called once, before the model is handed out.
+    pub unsafe fn init(&self, types: HashMap<String, RelationshipType>) {
+        *self.types.get() = types;
+    }
+}
+
+impl Deref for RelationshipTypes {
+    type Target = HashMap<String, RelationshipType>;
+
+    fn deref(&self) -> &HashMap<String, RelationshipType> {
+        unsafe { &*self.types.get() }
+    }
+}
+
+impl DerefMut for RelationshipTypes {
+    fn deref_mut(&mut self) -> &mut HashMap<String, RelationshipType> {
+        unsafe { &mut *self.types.get() }
+    }
+}
+
+impl<'a> IntoIterator for &'a RelationshipTypes {
+    type Item = (&'a String, &'a RelationshipType);
+    type IntoIter = hash_map::Iter<'a, String, RelationshipType>;
+
+    fn into_iter(self) -> hash_map::Iter<'a, String, RelationshipType> {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for RelationshipTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
 
 #[derive(Debug)]
 pub enum RelationshipType {
@@ -13,7 +68,7 @@ pub enum RelationshipType {
 }
 
 impl RelationshipType {
-    pub fn to_relationship<'a, 'b>(&'a self) -> Relationship<'b> {
+    pub fn to_relationship(&self) -> Relationship {
         match *self {
             RelationshipType::BelongsTo(_) => Relationship::BelongsTo(None),
             RelationshipType::HasOne(_) => Relationship::HasOne(None),
@@ -22,9 +77,28 @@ impl RelationshipType {
     }
 }
 
-#[derive(Debug)]
-pub enum Relationship<'a> {
-    BelongsTo(Option<&'a Record<'a>>),
-    HasOne(Option<Record<'a>>),
-    HasMany(Vec<Record<'a>>),
+/// Related records are referred to by identity, and owned by the `RecordSet` they were loaded into.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Relationship {
+    BelongsTo(Option<Identity>),
+    HasOne(Option<Identity>),
+    HasMany(Vec<Identity>),
+}
+
+/// The attribute holding the key of the parent of the `BelongsTo` relationship `name`, e.g.
+/// `customer_id` for `customer`.
+pub fn foreign_key(name: &str) -> String {
+    format!("{}_id", name)
+}
+
+/// The attributes holding the key of `target`, the parent of the `BelongsTo` relationship `name`:
+/// `foreign_key(name)` for a single key column, or `<name>_<column>` for each column of a composite
+/// key, e.g. `reading_sensor_id` and `reading_taken_at` for a `reading` keyed by `sensor_id` and
+/// `taken_at`.
+pub fn foreign_keys(name: &str, target: Model) -> Vec<String> {
+    if target.primary_key.len() == 1 {
+        vec![foreign_key(name)]
+    } else {
+        target.primary_key.iter().map(|column| format!("{}_{}", name, column)).collect()
+    }
 }
@@ -0,0 +1,287 @@
+//! Models defined at runtime from a JSON or TOML schema, for tools which don't know them at compile
+//! time. A schema lists models like `model!` does, relationships name their target by type:
+//!
+//! ```text
+//! [[models]]
+//! type = "shop.customer"
+//! primary_key = "customer_id"
+//! attributes = { name = "String" }
+//! relationships = { orders = "HasMany<shop.order>" }
+//! unique = [["name"]]
+//! ```
+//!
+//! `primary_key` is a column name, or an array of them for a composite key, and defaults to `"id"`.
+//! `key_generation` names a `KeyGeneration` other than `Custom`, and defaults to `"Serial"`.
+//! `attributes`, `relationships` and `unique` are optional. Loaded models live for the rest of the
+//! program, like the ones declared with `model!`.
+
+extern crate rustc_serialize;
+extern crate toml;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+use self::rustc_serialize::json::Json;
+
+use attribute::AttributeType;
+use error::{Error, Result};
+use model::{self, KeyGeneration, Model, ModelDef};
+use registry::Registry;
+use relationship::RelationshipType;
+
+/// Reads the models of a schema file, as TOML if its extension is `.toml` or as JSON otherwise.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Registry> {
+    let path = path.as_ref();
+    let mut source = String::new();
+    try!(File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+                         .map_err(|e| Error::InvalidSchema(format!("{}: {}", path.display(), e))));
+
+    let models = if path.extension().map_or(false, |extension| extension == "toml") {
+        try!(from_toml(&source))
+    } else {
+        try!(from_json(&source))
+    };
+
+    let mut registry = Registry::new();
+    for model in models {
+        try!(registry.register(model));
+    }
+    Ok(registry)
+}
+
+pub fn from_json(source: &str) -> Result<Vec<Model>> {
+    let json = try!(Json::from_str(source).map_err(|e| Error::InvalidSchema(e.to_string())));
+    build(&json)
+}
+
+pub fn from_toml(source: &str) -> Result<Vec<Model>> {
+    let mut parser = toml::Parser::new(source);
+    match parser.parse() {
+        Some(table) => build(&to_json(toml::Value::Table(table))),
+        None => {
+            let message = parser.errors.iter().map(|e| {
+                let (line, column) = parser.to_linecol(e.lo);
+                format!("{}:{}: {}", line + 1, column + 1, e.desc)
+            }).collect::<Vec<_>>().join(", ");
+            Err(Error::InvalidSchema(message))
+        },
+    }
+}
+
+fn to_json(value: toml::Value) -> Json {
+    match value {
+        toml::Value::String(s) | toml::Value::Datetime(s) => Json::String(s),
+        toml::Value::Integer(i) => Json::I64(i),
+        toml::Value::Float(f) => Json::F64(f),
+        toml::Value::Boolean(b) => Json::Boolean(b),
+        toml::Value::Array(values) => Json::Array(values.into_iter().map(to_json).collect()),
+        toml::Value::Table(table) => Json::Object(table.into_iter().map(|(key, value)| (key, to_json(value))).collect()),
+    }
+}
+
+// Relationships are set once every model has been allocated, so that they can refer to each other
+struct Pending {
+    def: ModelDef,
+    relationships: Vec<(String, String, String)>,
+}
+
+fn build(json: &Json) -> Result<Vec<Model>> {
+    let models = try!(json.find("models").and_then(Json::as_array)
+                          .ok_or_else(|| Error::InvalidSchema("expected an array of models".to_string())));
+
+    let mut pending = vec![];
+    for (i, json) in models.iter().enumerate() {
+        let context = format!("models[{}]", i);
+        let def = try!(parse_model(&context, json));
+        if pending.iter().any(|other: &Pending| other.def.ty == def.ty) {
+            return Err(Error::DuplicateModel(def.ty));
+        }
+
+        let mut relationships = vec![];
+        for (name, json) in try!(object(&context, json, "relationships")) {
+            let declaration = try!(json.as_string().ok_or_else(|| invalid(&context, name, "expected a string")));
+            let (kind, target) = try!(parse_relationship(declaration).ok_or_else(|| {
+                invalid(&context, name, &format!("expected `BelongsTo<type>`, `HasOne<type>` or `HasMany<type>`, found `{}`", declaration))
+            }));
+            relationships.push((name.clone(), kind.to_string(), target.to_string()));
+        }
+        pending.push(Pending { def: def, relationships: relationships });
+    }
+
+    for model in &pending {
+        for &(_, _, ref target) in &model.relationships {
+            if !pending.iter().any(|other| other.def.ty == *target) {
+                return Err(Error::UnknownModel(target.clone()));
+            }
+        }
+    }
+
+    // Every model is allocated before any relationship refers to it
+    let mut relationships = vec![];
+    let mut defs: Vec<Model> = vec![];
+    for model in pending {
+        relationships.push(model.relationships);
+        defs.push(unsafe { &*Box::into_raw(Box::new(model.def)) });
+    }
+
+    for (def, relationships) in defs.iter().zip(relationships) {
+        let mut types = HashMap::new();
+        for (name, kind, target) in relationships {
+            let target = *defs.iter().find(|other| other.ty == target).unwrap();
+            let relationship = match &kind[..] {
+                "BelongsTo" => RelationshipType::BelongsTo(target),
+                "HasOne" => RelationshipType::HasOne(target),
+                _ => RelationshipType::HasMany(target),
+            };
+            types.insert(name, relationship);
+        }
+        // Nothing reads the relationships until the models are returned
+        unsafe { def.relationships.init(types) };
+    }
+
+    Ok(defs)
+}
+
+fn parse_model(context: &str, json: &Json) -> Result<ModelDef> {
+    let ty = try!(json.find("type").and_then(Json::as_string)
+                      .ok_or_else(|| invalid(context, "type", "expected a string")));
+    let primary_key = match json.find("primary_key") {
+        Some(&Json::String(ref name)) => vec![name.clone()],
+        Some(&Json::Array(ref names)) if names.len() > 0 => {
+            let mut columns = vec![];
+            for name in names {
+                columns.push(try!(name.as_string().ok_or_else(|| invalid(context, "primary_key", "expected column names"))).to_string());
+            }
+            columns
+        },
+        Some(_) => return Err(invalid(context, "primary_key", "expected a column name or an array of column names")),
+        None => vec!["id".to_string()],
+    };
+    let mut def = model::model(ty, primary_key);
+
+    if let Some(json) = json.find("key_generation") {
+        def.key_generation = match json.as_string() {
+            Some("Manual") => KeyGeneration::Manual,
+            Some("Serial") => KeyGeneration::Serial,
+            Some("Identity") => KeyGeneration::Identity,
+            Some("UuidV4") => KeyGeneration::UuidV4,
+            Some("UuidV7") => KeyGeneration::UuidV7,
+            _ => return Err(invalid(context, "key_generation", "expected `Manual`, `Serial`, `Identity`, `UuidV4` or `UuidV7`")),
+        };
+    }
+
+    for (name, json) in try!(object(context, json, "attributes")) {
+        let ty = try!(json.as_string().and_then(AttributeType::from_name)
+                          .ok_or_else(|| invalid(context, name, "expected an attribute type such as `String` or `I32`")));
+        def.attributes.insert(name.clone(), ty);
+    }
+
+    if let Some(unique) = json.find("unique") {
+        let keys = try!(unique.as_array().ok_or_else(|| invalid(context, "unique", "expected an array of arrays")));
+        for key in keys {
+            let key = try!(key.as_array().ok_or_else(|| invalid(context, "unique", "expected an array of arrays")));
+            let mut names = vec![];
+            for name in key {
+                names.push(try!(name.as_string().ok_or_else(|| invalid(context, "unique", "expected attribute names"))).to_string());
+            }
+            def.unique.push(names);
+        }
+    }
+
+    Ok(def)
+}
+
+fn parse_relationship(declaration: &str) -> Option<(&str, &str)> {
+    if !declaration.ends_with('>') {
+        return None;
+    }
+    let mut parts = declaration[..declaration.len() - 1].splitn(2, '<');
+    match (parts.next(), parts.next()) {
+        (Some(kind), Some(target)) if ["BelongsTo", "HasOne", "HasMany"].contains(&kind) && !target.is_empty() => Some((kind, target)),
+        _ => None,
+    }
+}
+
+fn object<'a>(context: &str, json: &'a Json, name: &str) -> Result<Vec<(&'a String, &'a Json)>> {
+    match json.find(name) {
+        Some(&Json::Object(ref object)) => Ok(object.iter().collect()),
+        Some(_) => Err(invalid(context, name, "expected an object")),
+        None => Ok(vec![]),
+    }
+}
+
+fn invalid(context: &str, name: &str, message: &str) -> Error {
+    Error::InvalidSchema(format!("{}.{}: {}", context, name, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::AttributeType;
+    use error::Error;
+    use model::KeyGeneration;
+    use query::Query;
+    use relationship::RelationshipType;
+
+    const SCHEMA: &'static str = r#"
+        [[models]]
+        type = "shop.customer"
+        primary_key = "customer_id"
+        unique = [["name"]]
+
+        [models.attributes]
+        name = "String"
+
+        [models.relationships]
+        orders = "HasMany<shop.order>"
+
+        [[models]]
+        type = "shop.order"
+        key_generation = "UuidV4"
+        attributes = { price = "F32", customer_id = "I32" }
+        relationships = { customer = "BelongsTo<shop.customer>" }
+    "#;
+
+    #[test]
+    fn test_from_toml() {
+        let models = from_toml(SCHEMA).unwrap();
+        let (customer, order) = (models[0], models[1]);
+        assert_eq!(customer.ty, "shop.customer");
+        assert_eq!(customer.primary_key, vec!["customer_id".to_string()]);
+        assert_eq!(customer.attributes.get("name"), Some(&AttributeType::String));
+        assert_eq!(customer.unique, vec![vec!["name".to_string()]]);
+        assert_eq!(order.primary_key, vec!["id".to_string()]);
+        assert_eq!(order.key_generation, KeyGeneration::UuidV4);
+        match order.relationships.get("customer") {
+            Some(&RelationshipType::BelongsTo(target)) => assert_eq!(target.ty, "shop.customer"),
+            _ => panic!(),
+        }
+        assert_eq!(customer.foreign_key_in(order), vec!["customer_id".to_string()]);
+
+        let adapter = MemoryAdapter::new();
+        let mut record = order.create();
+        record.set("price", 10.0f32.into());
+        let key = order.insert_all(&adapter, vec![record]).unwrap().remove(0);
+        let orders = Query::new(order).where_("price").gt(&5.0f32.into()).get(&adapter).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders.first().unwrap().key(), key);
+    }
+
+    #[test]
+    fn test_from_json() {
+        let models = from_json(r#"{"models": [{"type": "tag", "attributes": {"label": "String"}}]}"#).unwrap();
+        assert_eq!(models[0].ty, "tag");
+
+        match from_json(r#"{"models": [{"type": "tag", "attributes": {"label": "Text"}}]}"#) {
+            Err(Error::InvalidSchema(ref message)) => assert!(message.starts_with("models[0].label")),
+            _ => panic!(),
+        }
+        match from_json(r#"{"models": [{"type": "tag", "relationships": {"posts": "HasMany<post>"}}]}"#) {
+            Err(Error::UnknownModel(ref ty)) if ty == "post" => {},
+            _ => panic!(),
+        }
+    }
+}
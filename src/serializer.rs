@@ -1,22 +1,147 @@
 use attribute::Attributes;
+use error::Result;
 use model::{Model, Record};
 
+/// Maps the rows of a model to records and back. Adapters use one to build the records of query
+/// results, see `Adapter::query_with`.
 pub trait Serializer {
-    fn extract(&self, model: Model, attributes: Attributes) -> Record;
+    fn extract(&self, model: Model, attributes: Attributes) -> Result<Record>;
+
+    fn serialize(&self, model: Model, record: &Record) -> Attributes;
 }
 
+/// Reads the primary key into `Record.id` and every other column into the attribute of the same
+/// name, widened to its declared type. Columns which don't match the model are reported rather than
+/// dropped.
 pub struct SimpleSerializer;
 
 impl Serializer for SimpleSerializer {
-    fn extract(&self, model: Model, attributes: Attributes) -> Record {
-        let mut record = model.create();
+    fn extract(&self, model: Model, attributes: Attributes) -> Result<Record> {
+        let mut record = model.create_empty();
         for (name, attribute) in attributes {
-            if name == model.primary_key {
+            if model.id_column() == Some(&name[..]) {
                 record.id = attribute;
             } else {
-                record.set(&name, attribute);
+                try!(record.try_set_coerced(&name, attribute));
+            }
+        }
+        record.mark_clean();
+        Ok(record)
+    }
+
+    fn serialize(&self, _: Model, record: &Record) -> Attributes {
+        record.attributes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adapter::Adapter;
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::{Attribute, Attributes};
+    use error::{Error, Result};
+    use model::{Model, Record};
+    use query::Query;
+
+    model! {
+        Author {
+            type: "author",
+            attributes: {
+                "name": String,
+            },
+            relationships: {
+                "books": HasMany<Book>,
+            },
+        },
+
+        Book {
+            type: "book",
+            attributes: {
+                "title": String,
+                "author_id": I32,
+            },
+            relationships: {
+                "author": BelongsTo<Author>,
+            },
+        }
+    }
+
+    // Stores strings in upper case
+    struct ShoutingSerializer;
+
+    impl Serializer for ShoutingSerializer {
+        fn extract(&self, model: Model, attributes: Attributes) -> Result<Record> {
+            let mut record = try!(SimpleSerializer.extract(model, attributes));
+            let names: Vec<String> = record.attributes.keys().cloned().collect();
+            for name in names {
+                if let Some(Attribute::String(Some(value))) = record.get(&name).cloned() {
+                    record.set(&name, value.to_uppercase().into());
+                }
             }
+            record.mark_clean();
+            Ok(record)
+        }
+
+        fn serialize(&self, model: Model, record: &Record) -> Attributes {
+            SimpleSerializer.serialize(model, record)
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut row = Attributes::new();
+        row.insert("id".to_string(), 1.into());
+        row.insert("name".to_string(), "Ann".to_string().into());
+        let record = SimpleSerializer.extract(&Author, row.clone()).unwrap();
+        assert_eq!(record.id, 1.into());
+        assert_eq!(record.get("name"), Some(&"Ann".to_string().into()));
+        assert!(!record.is_dirty());
+
+        row.insert("email".to_string(), "ann@example.com".to_string().into());
+        match SimpleSerializer.extract(&Author, row) {
+            Err(Error::UnknownAttribute(ref name)) if name == "email" => {},
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_query_with_serializer() {
+        let adapter = MemoryAdapter::new();
+        let mut author = Attributes::new();
+        author.insert("name".to_string(), "Ann".to_string().into());
+        adapter.insert(&Author, &author).unwrap();
+        let mut book = Attributes::new();
+        book.insert("title".to_string(), "Dune".to_string().into());
+        book.insert("author_id".to_string(), 1.into());
+        adapter.insert(&Book, &book).unwrap();
+
+        let books = Query::new(&Book).include(vec!["author"]).get_with(&adapter, &ShoutingSerializer).unwrap();
+        let book = books.first().unwrap();
+        assert_eq!(book.id, 2.into());
+        assert_eq!(book.get("title"), Some(&"DUNE".to_string().into()));
+        assert_eq!(books.get_one(book, "author").unwrap().get("name"), Some(&"ANN".to_string().into()));
+
+        let books = adapter.query(&Query::new(&Book)).unwrap();
+        assert_eq!(books.first().unwrap().get("title"), Some(&"Dune".to_string().into()));
+    }
+
+    #[test]
+    fn test_upsert_with_serializer() {
+        let adapter = MemoryAdapter::new();
+        let mut author = Author.create();
+        author.set("name", "Ann".to_string().into());
+        let stored = Author.upsert(vec![&author]).execute_with(&adapter, &ShoutingSerializer).unwrap();
+        assert_eq!(stored[0].get("name"), Some(&"ANN".to_string().into()));
+
+        // Rows which can't be read fail bulk writes rather than being skipped
+        let mut row = Attributes::new();
+        row.insert("email".to_string(), "ann@example.com".to_string().into());
+        adapter.insert(&Author, &row).unwrap();
+        match Query::new(&Author).unfiltered().delete_all(&adapter) {
+            Err(Error::UnknownAttribute(ref name)) if name == "email" => {},
+            _ => panic!(),
         }
-        record
+        assert_eq!(adapter.rows(&Author).len(), 2);
     }
 }
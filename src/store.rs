@@ -1,47 +1,278 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
 use adapter::Adapter;
-use attribute::Attribute;
-use model::{Model, Record};
+use attribute::{Attribute, Attributes};
+use error::{Error, Result};
+use model::{Identity, Model, Record};
 use query::Query;
 use serializer::Serializer;
 
-pub struct Store;
-
-impl Store {
-    pub fn new() -> Store {
-        Store
-    }
-
-    pub fn query<'a, A, S>(&'a mut self, model: &Model, adapter: &A, serializer: &'a S, query: &Query) -> Option<Vec<Record<'a>>>
-        where A: Adapter, S: Serializer
-    {
-        let attributes = match adapter.query(&query) {
-            Some(a) => a,
-            None => return None,
-        };
-        Some(attributes.into_iter().map(|attributes| serializer.extract(model, attributes)).collect())
-    }
-
-    pub fn find<'a, A, S>(&'a mut self, model: &Model, adapter: &A, serializer: &'a S, id: &Attribute) -> Option<Record<'a>>
-        where A: Adapter, S: Serializer
-    {
-        let query = Query::table(model.ty).filter(model.primary_key).eq(id).limit(1);
-        let attributes = match adapter.query(&query) {
-            Some(a) => a,
-            None => return None,
-        };
-
-        let attributes = attributes.into_iter().next().unwrap();
-        Some(serializer.extract(model, attributes))
-    }
-
-    pub fn find_by<'a, A, S>(&'a mut self, model: &Model, adapter: &A, serializer: &'a S, name: &str, filter: &Attribute) -> Option<Vec<Record<'a>>>
-        where A: Adapter, S: Serializer
-    {
-        let query = Query::table(model.ty).filter(name).eq(filter);
-        let attributes = match adapter.query(&query) {
-            Some(a) => a,
-            None => return None,
-        };
-        Some(attributes.into_iter().map(|attributes| serializer.extract(model, attributes)).collect())
+pub type RecordRef = Rc<RefCell<Record>>;
+
+/// A unit of work over an `Adapter`.
+///
+/// Every record loaded through the store is kept in an identity map keyed by `(ty, key)`, so loading
+/// the same row twice gives back the same `Record`. New and deleted records are tracked, dirty ones
+/// are picked up from the identity map, and all of them are written back in a single transaction by
+/// `commit()`. Only changed attributes are written.
+pub struct Store<'a, A: 'a, S: 'a> {
+    adapter: &'a A,
+    serializer: &'a S,
+    identity_map: HashMap<Identity, (Model, RecordRef)>,
+    new: Vec<(Model, RecordRef)>,
+    deleted: HashSet<Identity>,
+}
+
+impl<'a, A: Adapter, S: Serializer> Store<'a, A, S> {
+    pub fn new(adapter: &'a A, serializer: &'a S) -> Store<'a, A, S> {
+        Store {
+            adapter: adapter,
+            serializer: serializer,
+            identity_map: HashMap::new(),
+            new: vec![],
+            deleted: HashSet::new(),
+        }
+    }
+
+    pub fn find(&mut self, model: Model, id: &Attribute) -> Result<RecordRef> {
+        self.find_by_key(model, vec![id])
+    }
+
+    /// Like `find`, given one attribute per primary key column.
+    pub fn find_by_key(&mut self, model: Model, key: Vec<&Attribute>) -> Result<RecordRef> {
+        let identity = (&model.ty[..], key.iter().map(|&attribute| attribute.clone()).collect());
+        if self.deleted.contains(&identity) {
+            return Err(Error::RecordNotFound);
+        }
+
+        if let Some(&(_, ref record)) = self.identity_map.get(&identity) {
+            return Ok(record.clone());
+        }
+
+        let query = try!(model.find_by_key(key));
+        let records = try!(self.query(&query));
+        records.into_iter().next().ok_or(Error::RecordNotFound)
+    }
+
+    pub fn query(&mut self, query: &Query) -> Result<Vec<RecordRef>> {
+        let adapter = self.adapter;
+        let record_set = try!(adapter.query_with(query, self.serializer));
+        Ok(record_set.into_records().into_iter().filter_map(|record| {
+            let identity = record.identity();
+            if self.deleted.contains(&identity) {
+                None
+            } else {
+                Some(self.identity_map.entry(identity)
+                                      .or_insert_with(|| (query.model, Rc::new(RefCell::new(record))))
+                                      .1.clone())
+            }
+        }).collect())
+    }
+
+    /// Creates a new record which will be inserted on the next `commit()`.
+    pub fn create(&mut self, model: Model) -> RecordRef {
+        let record = Rc::new(RefCell::new(model.create()));
+        self.new.push((model, record.clone()));
+        record
+    }
+
+    /// Marks a record as deleted. A record which has never been committed is simply forgotten.
+    pub fn delete(&mut self, record: &RecordRef) -> Result<()> {
+        let count = self.new.len();
+        self.new.retain(|&(_, ref r)| !is_same(r, record));
+        if self.new.len() != count {
+            return Ok(());
+        }
+
+        let identity = try!(self.identity_of(record));
+        self.deleted.insert(identity);
+        Ok(())
+    }
+
+    /// Writes all pending changes in one transaction.
+    ///
+    /// Nothing is written if a new or dirty record is invalid, and nothing is forgotten if the
+    /// transaction fails, so `commit()` can be retried.
+    pub fn commit(&mut self) -> Result<()> {
+        for &(_, ref record) in &self.new {
+            try!(record.borrow().validate());
+        }
+        for (identity, &(_, ref record)) in &self.identity_map {
+            let record = record.borrow();
+            if record.is_dirty() && !self.deleted.contains(identity) {
+                try!(record.validate());
+            }
+        }
+
+        let serializer = self.serializer;
+        let identity_map = &self.identity_map;
+        let new = &self.new;
+        let deleted = &self.deleted;
+
+        let inserted = try!(self.adapter.transaction(|adapter| {
+            let mut inserted = vec![];
+
+            for &(model, ref record) in new {
+                let mut record = record.borrow_mut();
+                if record.id.is_null() {
+                    if let Some(id) = model.generate_id() {
+                        record.id = id;
+                    }
+                }
+                let mut attributes = changed_attributes(serializer, model, &record);
+                if let (Some(id_column), false) = (model.id_column(), record.id.is_null()) {
+                    attributes.insert(id_column.to_string(), record.id.clone());
+                }
+                let row = try!(adapter.insert(model, &attributes));
+                inserted.push(try!(serializer.extract(model, row)));
+            }
+
+            for (identity, &(model, ref record)) in identity_map {
+                let record = record.borrow();
+                if record.is_dirty() && !deleted.contains(identity) {
+                    let attributes = changed_attributes(serializer, model, &record);
+                    try!(adapter.update(model, &identity.1, &attributes));
+                }
+            }
+
+            for identity in deleted {
+                let &(model, _) = try!(identity_map.get(identity).ok_or(Error::RecordNotFound));
+                try!(adapter.delete(model, &identity.1));
+            }
+
+            Ok(inserted)
+        }));
+
+        for ((model, record), stored) in self.new.drain(..).zip(inserted.into_iter()) {
+            {
+                let mut record = record.borrow_mut();
+                record.id = stored.id;
+                record.attributes = stored.attributes;
+                record.mark_clean();
+            }
+            let identity = record.borrow().identity();
+            self.identity_map.insert(identity, (model, record));
+        }
+
+        for identity in self.deleted.drain() {
+            self.identity_map.remove(&identity);
+        }
+
+        for &(_, ref record) in self.identity_map.values() {
+            record.borrow_mut().mark_clean();
+        }
+
+        Ok(())
+    }
+
+    fn identity_of(&self, record: &RecordRef) -> Result<Identity> {
+        let identity = record.borrow().identity();
+
+        match self.identity_map.get(&identity) {
+            Some(&(_, ref r)) if is_same(r, record) => Ok(identity),
+            _ => Err(Error::RecordNotFound),
+        }
+    }
+}
+
+// Serializes only the changed attributes, so unchanged columns (and database defaults on insert)
+// are left alone.
+fn changed_attributes<S: Serializer>(serializer: &S, model: Model, record: &Record) -> Attributes {
+    serializer.serialize(model, record).into_iter().filter(|&(ref name, _)| record.is_changed(name)).collect()
+}
+
+fn is_same<T>(a: &Rc<T>, b: &Rc<T>) -> bool {
+    &**a as *const T == &**b as *const T
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::AttributeType;
+    use query::Query;
+    use serializer::SimpleSerializer;
+
+    model! {
+        User {
+            type: "user",
+            attributes: {
+                "name": String,
+            },
+            relationships: {},
+        },
+
+        Session {
+            type: "session",
+            key_generation: UuidV4,
+            attributes: {},
+            relationships: {},
+        }
+    }
+
+    #[test]
+    fn test_identity_map() {
+        let adapter = MemoryAdapter::new();
+        let serializer = SimpleSerializer;
+        let mut store = Store::new(&adapter, &serializer);
+
+        let user = store.create(&User);
+        user.borrow_mut().set("name", "Alice".to_string().into());
+        store.commit().unwrap();
+        assert_eq!(user.borrow().id, 1.into());
+        assert_eq!(adapter.rows(&User).len(), 1);
+
+        let found = store.find(&User, &1.into()).unwrap();
+        assert!(is_same(&user, &found));
+        assert_eq!(adapter.queries.get(), 0);
+
+        let mut other = Store::new(&adapter, &serializer);
+        let a = other.find(&User, &1.into()).unwrap();
+        let b = other.query(&Query::new(&User)).unwrap().pop().unwrap();
+        assert!(is_same(&a, &b));
+    }
+
+    #[test]
+    fn test_commit_dirty_and_deleted() {
+        let adapter = MemoryAdapter::new();
+        let serializer = SimpleSerializer;
+        let mut store = Store::new(&adapter, &serializer);
+
+        let user = store.create(&User);
+        store.commit().unwrap();
+        assert_eq!(adapter.rows(&User)[0].get("name"), None);
+
+        user.borrow_mut().set("name", "Bob".to_string().into());
+        store.commit().unwrap();
+        assert_eq!(adapter.rows(&User)[0].get("name"), Some(&"Bob".to_string().into()));
+        assert!(!user.borrow().is_dirty());
+
+        store.delete(&user).unwrap();
+        assert!(store.find(&User, &1.into()).is_err());
+        store.commit().unwrap();
+        assert_eq!(adapter.rows(&User).len(), 0);
+
+        let unmanaged = Rc::new(RefCell::new(User.create()));
+        assert!(store.delete(&unmanaged).is_err());
+    }
+
+    #[test]
+    fn test_generated_id() {
+        let adapter = MemoryAdapter::new();
+        let serializer = SimpleSerializer;
+        let mut store = Store::new(&adapter, &serializer);
+
+        let session = store.create(&Session);
+        assert!(session.borrow().id.is_null());
+        store.commit().unwrap();
+        let id = session.borrow().id.clone();
+        assert_eq!(id.ty(), AttributeType::String);
+        assert_eq!(adapter.rows(&Session)[0].get("id"), Some(&id));
+        assert!(is_same(&session, &store.find(&Session, &id).unwrap()));
     }
 }
@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use adapter::Adapter;
+use attribute::{Attribute, Attributes};
+use error::{Error, Result};
+use model::{Model, Record};
+use serializer::{Serializer, SimpleSerializer};
+
+/// Inserts records, updating the stored ones instead when they conflict on a key: the primary key
+/// by default, or a set of attributes declared `unique` in `model!`.
+pub struct Upsert<'a> {
+    pub model: Model,
+    pub records: Vec<&'a Record>,
+    pub key: Vec<&'a str>,
+    pub update: Option<Vec<&'a str>>,
+}
+
+impl<'a> Upsert<'a> {
+    pub fn new(model: Model, records: Vec<&'a Record>) -> Upsert<'a> {
+        Upsert {
+            model: model,
+            records: records,
+            key: model.primary_key.iter().map(|name| &name[..]).collect(),
+            update: None,
+        }
+    }
+
+    pub fn on_conflict(mut self, key: Vec<&'a str>) -> Upsert<'a> {
+        self.key = key;
+        self
+    }
+
+    /// Chooses the attributes to update on conflict, by default those changed in each record but
+    /// the key and the defaults left untouched. With none, conflicting records are left as they
+    /// are and aren't returned.
+    pub fn update(mut self, names: Vec<&'a str>) -> Upsert<'a> {
+        self.update = Some(names);
+        self
+    }
+
+    /// Returns the inserted and updated records as stored, grouped by the attributes they update.
+    /// Of the records with the same key, only the last one is written. Nothing is written if a
+    /// record is invalid.
+    pub fn execute<A: Adapter>(self, adapter: &A) -> Result<Vec<Record>> {
+        self.execute_with(adapter, &SimpleSerializer)
+    }
+
+    /// Like `execute`, with the returned records built by `serializer`.
+    pub fn execute_with<A: Adapter, S: Serializer>(self, adapter: &A, serializer: &S) -> Result<Vec<Record>> {
+        let model = self.model;
+        if !model.is_key(&self.key) {
+            return Err(Error::UnknownKey(self.key.join(",")));
+        }
+        for name in self.update.iter().flat_map(|update| update.iter()) {
+            if !model.attributes.contains_key(*name) {
+                return Err(Error::UnknownAttribute(name.to_string()));
+            }
+        }
+
+        if self.records.len() == 0 {
+            return Ok(vec![]);
+        }
+        for record in &self.records {
+            try!(record.validate());
+        }
+
+        // A row can't be updated twice by one statement. Like in SQL, nulls never conflict.
+        let mut keys = HashSet::new();
+        let mut records: Vec<&Record> = self.records.iter().rev().cloned().filter(|record| {
+            let key: Vec<Attribute> = self.key.iter().map(|name| {
+                record.value(name).cloned().unwrap_or(Attribute::String(None))
+            }).collect();
+            key.iter().any(|value| value.is_null()) || keys.insert(key)
+        }).collect();
+        records.reverse();
+
+        // Rows carry every attribute, so by default records changing different attributes are
+        // written by separate statements, each updating only what its records changed
+        let mut groups: Vec<(Vec<&str>, Vec<Attributes>)> = vec![];
+        for record in records {
+            let update = match self.update {
+                Some(ref update) => update.clone(),
+                None => {
+                    let mut update: Vec<&str> = record.changed_attributes().into_iter().filter(|name| {
+                        !record.is_defaulted(name) && !self.key.contains(name)
+                    }).collect();
+                    update.sort();
+                    update
+                },
+            };
+            let row = model.insert_row(record);
+            match groups.iter().position(|&(ref other, _)| *other == update) {
+                Some(i) => groups[i].1.push(row),
+                None => groups.push((update, vec![row])),
+            }
+        }
+
+        let mut stored = vec![];
+        for (update, rows) in groups {
+            stored.extend(try!(adapter.upsert(model, &rows, &self.key, &update)));
+        }
+        stored.into_iter().map(|row| serializer.extract(model, row)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use adapter::memory_adapter::MemoryAdapter;
+    use error::Error;
+
+    model! {
+        Account {
+            type: "account",
+            attributes: {
+                "email": String,
+                "name": String,
+                "plan": String,
+            },
+            relationships: {},
+            defaults: {
+                "plan": "free",
+            },
+            unique: [["email"],],
+        }
+    }
+
+    #[test]
+    fn test_upsert() {
+        let adapter = MemoryAdapter::new();
+
+        let mut alice = Account.create();
+        alice.set("email", "alice@example.com".to_string().into());
+        alice.set("name", "Alice".to_string().into());
+        let stored = Account.upsert(vec![&alice]).execute(&adapter).unwrap();
+        assert_eq!(stored[0].id, 1.into());
+
+        let mut renamed = Account.create();
+        renamed.set("email", "alice@example.com".to_string().into());
+        renamed.set("name", "Alicia".to_string().into());
+        let mut bob = Account.create();
+        bob.set("email", "bob@example.com".to_string().into());
+        let stored = Account.upsert(vec![&renamed, &bob]).on_conflict(vec!["email"]).execute(&adapter).unwrap();
+        assert_eq!(stored.iter().map(|record| record.id.clone()).collect::<Vec<_>>(), vec![1.into(), 2.into()]);
+        assert_eq!(stored[0].get_string("name").unwrap(), "Alicia");
+        assert_eq!(adapter.rows(&Account).len(), 2);
+
+        let stored = Account.upsert(vec![&alice]).on_conflict(vec!["email"]).update(vec![]).execute(&adapter).unwrap();
+        assert_eq!(stored.len(), 0);
+        assert_eq!(adapter.rows(&Account)[0]["name"], "Alicia".to_string().into());
+
+        match Account.upsert(vec![&alice]).on_conflict(vec!["name"]).execute(&adapter) {
+            Err(Error::UnknownKey(ref key)) if key == "name" => {},
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_upsert_duplicates() {
+        let adapter = MemoryAdapter::new();
+
+        let account = |email: &str, name: &str| {
+            let mut account = Account.create();
+            account.set("email", email.to_string().into());
+            account.set("name", name.to_string().into());
+            account
+        };
+        let first = account("a@example.com", "A1");
+        let mut second = account("a@example.com", "A2");
+        second.set("plan", "pro".to_string().into());
+        let other = account("b@example.com", "B");
+        let stored = Account.upsert(vec![&first, &other, &second]).on_conflict(vec!["email"]).execute(&adapter).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].get_string("email").unwrap(), "b@example.com");
+        assert_eq!(stored[1].get_string("name").unwrap(), "A2");
+        assert_eq!(adapter.rows(&Account).len(), 2);
+
+        // Only the changed attributes are updated by default, not the untouched defaults
+        let mut email_only = Account.create();
+        email_only.set("email", "a@example.com".to_string().into());
+        let stored = Account.upsert(vec![&email_only]).on_conflict(vec!["email"]).execute(&adapter).unwrap();
+        assert_eq!(stored.len(), 0);
+        assert_eq!(adapter.rows(&Account)[1]["name"], "A2".to_string().into());
+        assert_eq!(adapter.rows(&Account)[1]["plan"], "pro".to_string().into());
+    }
+
+    #[test]
+    fn test_upsert_mixed_changes() {
+        let adapter = MemoryAdapter::new();
+        let mut a = Account.create();
+        a.set("email", "a@example.com".to_string().into());
+        a.set("name", "A".to_string().into());
+        let mut b = Account.create();
+        b.set("email", "b@example.com".to_string().into());
+        b.set("name", "B".to_string().into());
+        Account.upsert(vec![&a, &b]).execute(&adapter).unwrap();
+
+        // Each record only updates the attributes it changed
+        let mut renamed = Account.create();
+        renamed.set("email", "a@example.com".to_string().into());
+        renamed.set("name", "A2".to_string().into());
+        let mut upgraded = Account.create();
+        upgraded.set("email", "b@example.com".to_string().into());
+        upgraded.set("plan", "pro".to_string().into());
+        let stored = Account.upsert(vec![&renamed, &upgraded]).on_conflict(vec!["email"]).execute(&adapter).unwrap();
+        assert_eq!(stored.len(), 2);
+
+        let rows = adapter.rows(&Account);
+        assert_eq!((&rows[0]["name"], &rows[0]["plan"]), (&"A2".to_string().into(), &"free".to_string().into()));
+        assert_eq!((&rows[1]["name"], &rows[1]["plan"]), (&"B".to_string().into(), &"pro".to_string().into()));
+    }
+}
@@ -0,0 +1,46 @@
+//! Random UUIDs, formatted as strings, for keys generated by the client (see
+//! `model::KeyGeneration`).
+
+extern crate rand;
+extern crate time;
+
+/// A random (version 4) UUID.
+pub fn v4() -> String {
+    let (high, low) = rand::random::<(u64, u64)>();
+    format(high, low, 4)
+}
+
+/// A version 7 UUID: the milliseconds since the Unix epoch followed by random bits, so that ids
+/// generated later sort after the earlier ones (at millisecond precision).
+pub fn v7() -> String {
+    let now = time::get_time();
+    let millis = now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000;
+    let (high, low) = rand::random::<(u64, u64)>();
+    format(millis << 16 | high & 0xffff, low, 7)
+}
+
+// Sets the version and the RFC 4122 variant bits
+fn format(high: u64, low: u64, version: u64) -> String {
+    let high = high & !0xf000 | version << 12;
+    let low = low & !(0xc << 60) | 0x8 << 60;
+    format!("{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32, high >> 16 & 0xffff, high & 0xffff, low >> 48, low & 0xffff_ffff_ffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid() {
+        let id = v4();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!(["8", "9", "a", "b"].contains(&&id[19..20]));
+        assert!(v4() != id);
+
+        let first = v7();
+        assert_eq!(&first[14..15], "7");
+        assert!(v7()[..8] >= first[..8]);
+    }
+}
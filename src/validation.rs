@@ -0,0 +1,291 @@
+//! Constraints on attributes, declared in `model!` and checked by `Record::validate()` before
+//! records are written:
+//!
+//! ```text
+//! validations: {
+//!     "title": [Presence, Length(1, 100)],
+//!     "rating": [Range(1, 5)],
+//!     "isbn": [Format("^[0-9]{13}$")],
+//!     "status": [Inclusion("draft", "published")],
+//!     "slug": [Custom(check_slug)],
+//! },
+//! ```
+//!
+//! Validations other than `Presence` accept null values.
+
+extern crate regex;
+#[cfg(feature="rustc-serialize")]
+extern crate rustc_serialize;
+
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt;
+
+use self::regex::Regex;
+#[cfg(feature="rustc-serialize")]
+use self::rustc_serialize::json::{Json, ToJson};
+
+use attribute::Attribute;
+use error::{self, Error};
+
+pub type Validations = HashMap<String, Vec<Validation>>;
+
+#[derive(Debug)]
+pub enum Validation {
+    /// The attribute is neither null nor an empty string.
+    Presence,
+    /// A string has between `min` and `max` characters, inclusive.
+    Length(usize, usize),
+    /// A number is between `min` and `max`, inclusive.
+    Range(f64, f64),
+    /// A string matches the regular expression.
+    Format(Regex),
+    /// The attribute is one of the values.
+    Inclusion(Vec<Attribute>),
+    /// Returns the error message if the attribute is invalid.
+    Custom(fn(&Attribute) -> Result<(), String>),
+}
+
+impl Validation {
+    /// A `Format` validation, fails with `InvalidParameter` if the pattern isn't a valid regular
+    /// expression.
+    pub fn format(pattern: &str) -> error::Result<Validation> {
+        match Regex::new(pattern) {
+            Ok(regex) => Ok(Validation::Format(regex)),
+            Err(_) => Err(Error::InvalidParameter(pattern.to_string())),
+        }
+    }
+
+    /// Checks `attribute`, returns the error message if it is invalid.
+    pub fn check(&self, attribute: &Attribute) -> Result<(), String> {
+        if attribute.is_null() {
+            return match *self {
+                Validation::Presence => Err("can't be blank".to_string()),
+                _ => Ok(()),
+            };
+        }
+
+        match *self {
+            Validation::Presence => match *attribute {
+                Attribute::String(Some(ref value)) if value.is_empty() => Err("can't be blank".to_string()),
+                _ => Ok(()),
+            },
+            Validation::Length(min, max) => match *attribute {
+                Attribute::String(Some(ref value)) => {
+                    let length = value.chars().count();
+                    if length < min {
+                        Err(format!("is too short (minimum is {} characters)", min))
+                    } else if length > max {
+                        Err(format!("is too long (maximum is {} characters)", max))
+                    } else {
+                        Ok(())
+                    }
+                },
+                _ => Err("is not a string".to_string()),
+            },
+            Validation::Range(min, max) => match number(attribute) {
+                Some(value) if value < min => Err(format!("must be greater than or equal to {}", min)),
+                Some(value) if value > max => Err(format!("must be less than or equal to {}", max)),
+                Some(_) => Ok(()),
+                None => Err("is not a number".to_string()),
+            },
+            Validation::Format(ref regex) => match *attribute {
+                Attribute::String(Some(ref value)) if regex.is_match(value) => Ok(()),
+                _ => Err("is invalid".to_string()),
+            },
+            Validation::Inclusion(ref values) => {
+                if values.contains(attribute) {
+                    Ok(())
+                } else {
+                    Err("is not included in the list".to_string())
+                }
+            },
+            Validation::Custom(check) => check(attribute),
+        }
+    }
+}
+
+fn number(attribute: &Attribute) -> Option<f64> {
+    match *attribute {
+        Attribute::I8(Some(value)) => Some(value as f64),
+        Attribute::I16(Some(value)) => Some(value as f64),
+        Attribute::I32(Some(value)) => Some(value as f64),
+        Attribute::I64(Some(value)) => Some(value as f64),
+        Attribute::F32(Some(value)) => Some(value as f64),
+        Attribute::F64(Some(value)) => Some(value),
+        _ => None,
+    }
+}
+
+/// The error messages of an invalid record, keyed by attribute.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Errors {
+    messages: BTreeMap<String, Vec<String>>,
+}
+
+impl Errors {
+    pub fn new() -> Errors {
+        Errors {
+            messages: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, message: String) {
+        self.messages.entry(name.to_string()).or_insert_with(Vec::new).push(message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// The messages about `name`, empty if it is valid.
+    pub fn get(&self, name: &str) -> &[String] {
+        self.messages.get(name).map_or(&[], |messages| &messages[..])
+    }
+
+    /// The invalid attributes along with their messages, ordered by name.
+    pub fn iter(&self) -> btree_map::Iter<String, Vec<String>> {
+        self.messages.iter()
+    }
+}
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages: Vec<String> = self.messages.iter().flat_map(|(name, messages)| {
+            messages.iter().map(move |message| format!("{} {}", name, message))
+        }).collect();
+        write!(f, "{}", messages.join(", "))
+    }
+}
+
+/// A JSON:API document with one error object per message, pointing at the attribute.
+#[cfg(feature="rustc-serialize")]
+impl ToJson for Errors {
+    fn to_json(&self) -> Json {
+        let mut errors = vec![];
+        for (name, messages) in &self.messages {
+            for message in messages {
+                let mut source = BTreeMap::new();
+                source.insert("pointer".to_string(), Json::String(format!("/data/attributes/{}", name)));
+
+                let mut error = BTreeMap::new();
+                error.insert("status".to_string(), Json::String("422".to_string()));
+                error.insert("title".to_string(), Json::String("Invalid Attribute".to_string()));
+                error.insert("detail".to_string(), Json::String(format!("{} {}", name, message)));
+                error.insert("source".to_string(), Json::Object(source));
+                errors.push(Json::Object(error));
+            }
+        }
+
+        let mut document = BTreeMap::new();
+        document.insert("errors".to_string(), Json::Array(errors));
+        Json::Object(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature="rustc-serialize")]
+    use super::rustc_serialize::json::{Json, ToJson};
+
+    use adapter::memory_adapter::MemoryAdapter;
+    use attribute::Attribute;
+    use error::Error;
+    use serializer::SimpleSerializer;
+    use store::Store;
+
+    model! {
+        Book {
+            type: "book",
+            attributes: {
+                "title": String,
+                "isbn": String,
+                "rating": I32,
+                "status": String,
+                "slug": String,
+            },
+            relationships: {},
+            validations: {
+                "title": [Presence, Length(1, 10)],
+                "isbn": [Format("^[0-9]{13}$")],
+                "rating": [Range(1, 5)],
+                "status": [Presence, Inclusion("draft", "published")],
+                "slug": [Custom(check_slug)],
+            },
+        }
+    }
+
+    fn check_slug(attribute: &Attribute) -> Result<(), String> {
+        match *attribute {
+            Attribute::String(Some(ref slug)) if slug.contains(' ') => Err("can't contain spaces".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn errors_of(result: ::error::Result<()>) -> Errors {
+        match result {
+            Err(Error::InvalidRecord(errors)) => errors,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut book = Book.create();
+        book.set("title", "A very long title".to_string().into());
+        book.set("isbn", "978-0".to_string().into());
+        book.set("rating", 6.into());
+        book.set("slug", "a slug".to_string().into());
+
+        let errors = errors_of(book.validate());
+        assert_eq!(errors.get("title"), ["is too long (maximum is 10 characters)".to_string()]);
+        assert_eq!(errors.get("isbn"), ["is invalid".to_string()]);
+        assert_eq!(errors.get("rating"), ["must be less than or equal to 5".to_string()]);
+        assert_eq!(errors.get("status"), ["can't be blank".to_string()]);
+        assert_eq!(errors.get("slug"), ["can't contain spaces".to_string()]);
+        assert_eq!(errors.iter().count(), 5);
+
+        book.set("status", "sold".to_string().into());
+        assert_eq!(errors_of(book.validate()).get("status"), ["is not included in the list".to_string()]);
+
+        book.set("title", "Dune".to_string().into());
+        book.set("isbn", "9780441013593".to_string().into());
+        book.set("rating", Attribute::I32(None));
+        book.set("status", "draft".to_string().into());
+        book.set("slug", "dune".to_string().into());
+        assert!(book.validate().is_ok());
+
+        assert!(Validation::format("[0-9").is_err());
+    }
+
+    #[test]
+    fn test_refuse_invalid_records() {
+        let adapter = MemoryAdapter::new();
+        assert!(Book.insert_all(&adapter, vec![Book.create()]).is_err());
+
+        let serializer = SimpleSerializer;
+        let mut store = Store::new(&adapter, &serializer);
+        let book = store.create(&Book);
+        assert!(store.commit().is_err());
+        assert_eq!(adapter.rows(&Book).len(), 0);
+
+        book.borrow_mut().set("title", "Dune".to_string().into());
+        book.borrow_mut().set("status", "draft".to_string().into());
+        store.commit().unwrap();
+        assert_eq!(adapter.rows(&Book).len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature="rustc-serialize")]
+    fn test_to_json() {
+        let mut book = Book.create();
+        book.set("title", "Dune".to_string().into());
+        let json = errors_of(book.validate()).to_json();
+        assert_eq!(json, Json::from_str(r#"{"errors": [{
+            "status": "422",
+            "title": "Invalid Attribute",
+            "detail": "status can't be blank",
+            "source": {"pointer": "/data/attributes/status"}
+        }]}"#).unwrap());
+    }
+}